@@ -1,4 +1,8 @@
+use std::collections::HashMap;
+use std::time::Duration;
 use crate::executer::value::Type;
+use crate::executer::value::Value;
+use crate::executer::value::Ident;
 
 trait DisplayError {
     fn display_error(&self) -> String;
@@ -44,12 +48,23 @@ pub struct CannotDivError {
     pub right: String,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct DivisionByZeroError {
+    pub left: String,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct CannotModError {
     pub left: String,
     pub right: String,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct CannotPowError {
+    pub left: String,
+    pub right: String,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct CannotCompareError {
     pub left: String,
@@ -113,10 +128,64 @@ pub struct InvalidCastNumberError {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct ItsAConstantError {
+pub struct CannotMutateError {
     pub var_name: String
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct InvalidArgumentError {
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DestructureMismatchError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyNotFoundError {
+    pub key: String,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OperatorNotFoundError {
+    pub op: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecursionLimitExceededError {
+    pub limit: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StepLimitExceededError {
+    pub limit: u64,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimeoutError {
+    pub timeout: Duration,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AssertionFailedError {
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MaxLengthExceededError {
+    pub limit: usize,
+    pub length: usize,
+}
+
 impl DisplayError for VarNotFoundError {
     fn display_error(&self) -> String {
         format!("Variable {} not found", self.var_name)
@@ -162,12 +231,24 @@ impl DisplayError for CannotDivError {
     }
 }
 
+impl DisplayError for DivisionByZeroError {
+    fn display_error(&self) -> String {
+        format!("Cannot divide {} by zero", self.left)
+    }
+}
+
 impl DisplayError for CannotModError {
     fn display_error(&self) -> String {
         format!("Cannot mod {} by {}", self.left, self.right)
     }
 }
 
+impl DisplayError for CannotPowError {
+    fn display_error(&self) -> String {
+        format!("Cannot raise {} to the power of {}", self.left, self.right)
+    }
+}
+
 impl DisplayError for CannotCompareError {
     fn display_error(&self) -> String {
         format!("Cannot compare {} and {}", self.left, self.right)
@@ -237,9 +318,69 @@ impl DisplayError for InvalidCastNumberError {
     }
 }
 
-impl DisplayError for ItsAConstantError {
+impl DisplayError for CannotMutateError {
     fn display_error(&self) -> String {
-        format!("its a constant: {}", self.var_name)
+        format!("Cannot mutate immutable variable {}", self.var_name)
+    }
+}
+
+impl DisplayError for InvalidArgumentError {
+    fn display_error(&self) -> String {
+        format!("Invalid argument for {}: {}", self.name, self.reason)
+    }
+}
+
+impl DisplayError for DestructureMismatchError {
+    fn display_error(&self) -> String {
+        format!("Cannot destructure {} values into {} names", self.found, self.expected)
+    }
+}
+
+impl DisplayError for ParseError {
+    fn display_error(&self) -> String {
+        format!("Parse error: {}", self.message)
+    }
+}
+
+impl DisplayError for KeyNotFoundError {
+    fn display_error(&self) -> String {
+        format!("Key {} not found in {}", self.key, self.name)
+    }
+}
+
+impl DisplayError for OperatorNotFoundError {
+    fn display_error(&self) -> String {
+        format!("Operator `{}` not found", self.op)
+    }
+}
+
+impl DisplayError for RecursionLimitExceededError {
+    fn display_error(&self) -> String {
+        format!("Function calls nest deeper than the limit of {}", self.limit)
+    }
+}
+
+impl DisplayError for StepLimitExceededError {
+    fn display_error(&self) -> String {
+        format!("Evaluation exceeded the limit of {} steps", self.limit)
+    }
+}
+
+impl DisplayError for TimeoutError {
+    fn display_error(&self) -> String {
+        format!("Evaluation did not finish within {:?}", self.timeout)
+    }
+}
+
+impl DisplayError for AssertionFailedError {
+    fn display_error(&self) -> String {
+        format!("Assertion failed: {}", self.message)
+    }
+}
+
+impl DisplayError for MaxLengthExceededError {
+    fn display_error(&self) -> String {
+        format!("Length {} exceeds the maximum of {}", self.length, self.limit)
     }
 }
 
@@ -253,7 +394,9 @@ pub enum Error {
     CannotSub(CannotSubError),
     CannotMul(CannotMulError),
     CannotDiv(CannotDivError),
+    DivisionByZero(DivisionByZeroError),
     CannotMod(CannotModError),
+    CannotPow(CannotPowError),
     CannotCompare(CannotCompareError),
     IsBuiltin(IsBuiltinError),
     FunctionNotFound(FunctionNotFoundError),
@@ -265,5 +408,114 @@ pub enum Error {
     EnumNotFound(EnumNotFoundError),
     FieldEnumNotFound(FieldEnumNotFoundError),
     InvalidCastNumber(InvalidCastNumberError),
-    ItsAConstant(ItsAConstantError)
+    CannotMutate(CannotMutateError),
+    InvalidArgument(InvalidArgumentError),
+    MaxLengthExceeded(MaxLengthExceededError),
+    RecursionLimitExceeded(RecursionLimitExceededError),
+    StepLimitExceeded(StepLimitExceededError),
+    Timeout(TimeoutError),
+    DestructureMismatch(DestructureMismatchError),
+    Parse(ParseError),
+    OperatorNotFound(OperatorNotFoundError),
+    KeyNotFound(KeyNotFoundError),
+    AssertionFailed(AssertionFailedError),
+    Break,
+    Continue,
+    Return(Value)
+}
+
+impl Error {
+    pub fn is_control_flow(&self) -> bool {
+        matches!(self, Error::Break | Error::Continue | Error::Return(_))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::VarNotFound(_) => "VarNotFound",
+            Error::VarAlreadyDefined(_) => "VarAlreadyDefined",
+            Error::TypeMismatch(_) => "TypeMismatch",
+            Error::CannotAdd(_) => "CannotAdd",
+            Error::CannotSub(_) => "CannotSub",
+            Error::CannotMul(_) => "CannotMul",
+            Error::CannotDiv(_) => "CannotDiv",
+            Error::DivisionByZero(_) => "DivisionByZero",
+            Error::CannotMod(_) => "CannotMod",
+            Error::CannotPow(_) => "CannotPow",
+            Error::CannotCompare(_) => "CannotCompare",
+            Error::IsBuiltin(_) => "IsBuiltin",
+            Error::FunctionNotFound(_) => "FunctionNotFound",
+            Error::IndexOutOfBounds(_) => "IndexOutOfBounds",
+            Error::StructNotFound(_) => "StructNotFound",
+            Error::AttrNotFound(_) => "AttrNotFound",
+            Error::FunctionArgumentMismatch(_) => "FunctionArgumentMismatch",
+            Error::FileNotFound(_) => "FileNotFound",
+            Error::EnumNotFound(_) => "EnumNotFound",
+            Error::FieldEnumNotFound(_) => "FieldEnumNotFound",
+            Error::InvalidCastNumber(_) => "InvalidCastNumber",
+            Error::CannotMutate(_) => "CannotMutate",
+            Error::InvalidArgument(_) => "InvalidArgument",
+            Error::MaxLengthExceeded(_) => "MaxLengthExceeded",
+            Error::RecursionLimitExceeded(_) => "RecursionLimitExceeded",
+            Error::StepLimitExceeded(_) => "StepLimitExceeded",
+            Error::Timeout(_) => "Timeout",
+            Error::DestructureMismatch(_) => "DestructureMismatch",
+            Error::Parse(_) => "Parse",
+            Error::OperatorNotFound(_) => "OperatorNotFound",
+            Error::KeyNotFound(_) => "KeyNotFound",
+            Error::AssertionFailed(_) => "AssertionFailed",
+            Error::Break => "Break",
+            Error::Continue => "Continue",
+            Error::Return(_) => "Return",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::VarNotFound(e) => e.display_error(),
+            Error::VarAlreadyDefined(e) => e.display_error(),
+            Error::TypeMismatch(e) => e.display_error(),
+            Error::CannotAdd(e) => e.display_error(),
+            Error::CannotSub(e) => e.display_error(),
+            Error::CannotMul(e) => e.display_error(),
+            Error::CannotDiv(e) => e.display_error(),
+            Error::DivisionByZero(e) => e.display_error(),
+            Error::CannotMod(e) => e.display_error(),
+            Error::CannotPow(e) => e.display_error(),
+            Error::CannotCompare(e) => e.display_error(),
+            Error::IsBuiltin(e) => e.display_error(),
+            Error::FunctionNotFound(e) => e.display_error(),
+            Error::IndexOutOfBounds(e) => e.display_error(),
+            Error::StructNotFound(e) => e.display_error(),
+            Error::AttrNotFound(e) => e.display_error(),
+            Error::FunctionArgumentMismatch(e) => e.display_error(),
+            Error::FileNotFound(e) => e.display_error(),
+            Error::EnumNotFound(e) => e.display_error(),
+            Error::FieldEnumNotFound(e) => e.display_error(),
+            Error::InvalidCastNumber(e) => e.display_error(),
+            Error::CannotMutate(e) => e.display_error(),
+            Error::InvalidArgument(e) => e.display_error(),
+            Error::MaxLengthExceeded(e) => e.display_error(),
+            Error::RecursionLimitExceeded(e) => e.display_error(),
+            Error::StepLimitExceeded(e) => e.display_error(),
+            Error::Timeout(e) => e.display_error(),
+            Error::DestructureMismatch(e) => e.display_error(),
+            Error::Parse(e) => e.display_error(),
+            Error::OperatorNotFound(e) => e.display_error(),
+            Error::KeyNotFound(e) => e.display_error(),
+            Error::AssertionFailed(e) => e.display_error(),
+            Error::Break => "break outside of a loop".to_string(),
+            Error::Continue => "continue outside of a loop".to_string(),
+            Error::Return(_) => "return outside of a function".to_string(),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut fields = HashMap::new();
+        fields.insert(Ident("kind".to_string()), Value::String(self.kind().to_string()));
+        fields.insert(Ident("message".to_string()), Value::String(self.message()));
+        Value::CallStruct {
+            name: "Error".to_string(),
+            fields,
+        }
+    }
 }
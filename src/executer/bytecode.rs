@@ -0,0 +1,504 @@
+use super::*;
+
+/// A flat instruction; every compiled expression leaves exactly one value
+/// on the stack.
+#[derive(Debug, Clone)]
+pub enum Instr {
+    // An index into the chunk's constant pool.
+    Const(usize),
+    Load(String),
+    Define {
+        name: String,
+        mutable: bool,
+        type_: Option<Type>,
+    },
+    Store(String),
+    Update(IOp, String),
+    Binary(Op),
+    Unary(UnaryOp),
+    // `x op k` where the constant `k` leaves integers unchanged, and a unary
+    // operator applied twice. Both only apply the operators to other values,
+    // so those keep their results and errors.
+    Identity(Op, usize),
+    Involution(UnaryOp),
+    And(usize),
+    Or(usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    Pop,
+    PushScope,
+    PopScope,
+    CheckLength(usize),
+    MakeList(usize),
+    Resolve {
+        name: String,
+        argc: usize,
+    },
+    Call(usize),
+    EnterLoop {
+        exit: usize,
+    },
+    Iterate {
+        exit: usize,
+    },
+    Next {
+        name: String,
+        exit: usize,
+    },
+    SetLast,
+    ExitLoop,
+    Break,
+    Continue,
+    // Nodes without a dedicated instruction run on the tree-walker.
+    Eval(Expr),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<Instr>,
+    pub constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn pool_size(&self) -> usize {
+        self.constants.len()
+    }
+}
+
+pub fn compile(expr: &Expr) -> Chunk {
+    let mut compiler = Compiler {
+        code: Vec::new(),
+        constants: Vec::new(),
+        pool: HashMap::new(),
+    };
+    compiler.expr(expr);
+    Chunk { code: compiler.code, constants: compiler.constants }
+}
+
+// Floats are keyed by their bits since `f64` is not `Hash`.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Int(i64),
+    Float(u64),
+    String(String),
+    Bool(bool),
+    None,
+}
+
+struct Compiler {
+    code: Vec<Instr>,
+    constants: Vec<Value>,
+    pool: HashMap<ConstKey, usize>,
+}
+
+impl Compiler {
+    // Identical literals share one slot in the constant pool.
+    fn intern(&mut self, key: ConstKey, value: Value) -> usize {
+        let constants = &mut self.constants;
+        *self.pool.entry(key).or_insert_with(|| {
+            constants.push(value);
+            constants.len() - 1
+        })
+    }
+
+    fn constant(&mut self, key: ConstKey, value: Value) -> usize {
+        let index = self.intern(key, value);
+        self.emit(Instr::Const(index))
+    }
+
+    fn none(&mut self) -> usize {
+        self.constant(ConstKey::None, Value::None)
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize) {
+        let next = self.code.len();
+        match &mut self.code[at] {
+            Instr::And(target)
+            | Instr::Or(target)
+            | Instr::Jump(target)
+            | Instr::JumpIfFalse(target)
+            | Instr::EnterLoop { exit: target }
+            | Instr::Iterate { exit: target }
+            | Instr::Next { exit: target, .. } => *target = next,
+            instr => unreachable!("cannot patch {:?}", instr),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Empty => {
+                self.none();
+            }
+            Expr::Literal { value } => {
+                match value {
+                    Literal::Int(n) => self.constant(ConstKey::Int(*n), Value::Int(*n)),
+                    Literal::Float(n) => self.constant(ConstKey::Float(n.to_bits()), Value::Float(*n)),
+                    Literal::String(s) => self.constant(ConstKey::String(s.clone()), Value::String(s.clone())),
+                    Literal::Bool(b) => self.constant(ConstKey::Bool(*b), Value::Bool(*b)),
+                };
+            }
+            Expr::Ident { ident } => {
+                self.emit(Instr::Load(ident.clone()));
+            }
+            Expr::BinOp { op: op @ (Op::And | Op::Or), left, right } => {
+                self.expr(left);
+                let jump = match op {
+                    Op::And => self.emit(Instr::And(0)),
+                    _ => self.emit(Instr::Or(0)),
+                };
+                self.expr(right);
+                self.emit(Instr::Binary(op.clone()));
+                self.patch(jump);
+            }
+            Expr::BinOp { op, left, right } => {
+                self.expr(left);
+                match (op, &**right) {
+                    (Op::Add | Op::Sub, Expr::Literal { value: Literal::Int(n) }) if *n == 0 => {
+                        let index = self.intern(ConstKey::Int(0), Value::Int(0));
+                        self.emit(Instr::Identity(op.clone(), index));
+                    }
+                    (Op::Mul | Op::Div, Expr::Literal { value: Literal::Int(n) }) if *n == 1 => {
+                        let index = self.intern(ConstKey::Int(1), Value::Int(1));
+                        self.emit(Instr::Identity(op.clone(), index));
+                    }
+                    _ => {
+                        self.expr(right);
+                        self.emit(Instr::Binary(op.clone()));
+                    }
+                }
+            }
+            Expr::UnaryOp { op, value } => match &**value {
+                Expr::UnaryOp { op: inner, value } if inner == op => {
+                    self.expr(value);
+                    self.emit(Instr::Involution(op.clone()));
+                }
+                _ => {
+                    self.expr(value);
+                    self.emit(Instr::Unary(op.clone()));
+                }
+            },
+            Expr::IfThen { cond, then } => {
+                self.expr(cond);
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                self.expr(then);
+                let end = self.emit(Instr::Jump(0));
+                self.patch(skip);
+                self.none();
+                self.patch(end);
+            }
+            Expr::IfThenElse { cond, then, else_ } => {
+                self.expr(cond);
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                self.expr(then);
+                let end = self.emit(Instr::Jump(0));
+                self.patch(skip);
+                self.expr(else_);
+                self.patch(end);
+            }
+            Expr::Assign { name, value, mutable, type_ } => {
+                self.expr(value);
+                self.emit(Instr::Define {
+                    name: name.clone(),
+                    mutable: *mutable,
+                    type_: type_.clone(),
+                });
+            }
+            Expr::AssignMany { assigns } => {
+                for (i, assign) in assigns.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Instr::Pop);
+                    }
+                    self.expr(assign);
+                }
+            }
+            Expr::SetVar { name, value } => {
+                self.expr(value);
+                self.emit(Instr::Store(name.clone()));
+            }
+            Expr::IOp { op, name, value } => {
+                self.expr(value);
+                self.emit(Instr::Update(op.clone(), name.clone()));
+            }
+            Expr::Block { body } => {
+                self.emit(Instr::PushScope);
+                if body.is_empty() {
+                    self.none();
+                }
+                for (i, expr) in body.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Instr::Pop);
+                    }
+                    self.expr(expr);
+                }
+                self.emit(Instr::PopScope);
+            }
+            Expr::List { elems } if !elems.iter().any(|e| matches!(e, Expr::Spread { .. })) => {
+                self.emit(Instr::CheckLength(elems.len()));
+                for elem in elems {
+                    self.expr(elem);
+                }
+                self.emit(Instr::MakeList(elems.len()));
+            }
+            Expr::Call { name, args } => {
+                self.emit(Instr::Resolve { name: name.clone(), argc: args.len() });
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Instr::Call(args.len()));
+            }
+            Expr::While { cond, body } => {
+                let enter = self.emit(Instr::EnterLoop { exit: 0 });
+                let top = self.code.len();
+                self.expr(cond);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.expr(body);
+                self.emit(Instr::Pop);
+                self.emit(Instr::Jump(top));
+                self.patch(exit);
+                self.patch(enter);
+                self.emit(Instr::ExitLoop);
+            }
+            Expr::For { name, iter, body } if matches!(**name, Expr::Ident { .. }) => {
+                let name = match &**name {
+                    Expr::Ident { ident } => ident.clone(),
+                    _ => unreachable!(),
+                };
+                self.expr(iter);
+                self.emit(Instr::PushScope);
+                let enter = self.emit(Instr::Iterate { exit: 0 });
+                let top = self.emit(Instr::Next { name, exit: 0 });
+                self.expr(body);
+                self.emit(Instr::SetLast);
+                self.emit(Instr::Jump(top));
+                self.patch(enter);
+                self.patch(top);
+                self.emit(Instr::ExitLoop);
+                self.emit(Instr::PopScope);
+            }
+            Expr::Break => {
+                self.emit(Instr::Break);
+            }
+            Expr::Continue => {
+                self.emit(Instr::Continue);
+            }
+            _ => {
+                self.emit(Instr::Eval(expr.clone()));
+            }
+        }
+    }
+}
+
+struct LoopFrame {
+    exit: usize,
+    resume: usize,
+    stack: usize,
+    scopes: usize,
+    last: Value,
+    items: Option<Box<dyn Iterator<Item = Value>>>,
+}
+
+impl Vm {
+    /// Runs a compiled chunk; results and errors match `eval_expr` on the
+    /// expression it was compiled from.
+    pub fn run_chunk(&mut self, chunk: &Chunk) -> Result<Value, Error> {
+        let depth = self.scopes.len();
+        let result = self.run_code(chunk);
+        while self.scopes.len() > depth {
+            self.pop_scope();
+        }
+        result
+    }
+
+    fn run_code(&mut self, chunk: &Chunk) -> Result<Value, Error> {
+        let code = &chunk.code;
+        let mut stack = Vec::new();
+        let mut loops = Vec::new();
+        let mut ip = 0;
+        while ip < code.len() {
+            self.count_step()?;
+            ip = match self.step(&code[ip], ip, &chunk.constants, &mut stack, &mut loops) {
+                Ok(next) => next,
+                Err(error) => {
+                    let frame = match (&error, loops.last()) {
+                        (Error::Break | Error::Continue, Some(frame)) => frame,
+                        _ => return Err(error),
+                    };
+                    stack.truncate(frame.stack);
+                    while self.scopes.len() > frame.scopes {
+                        self.pop_scope();
+                    }
+                    match error {
+                        Error::Break => frame.exit,
+                        _ => frame.resume,
+                    }
+                }
+            };
+        }
+        Ok(stack.pop().unwrap_or(Value::None))
+    }
+
+    fn step(&mut self, instr: &Instr, ip: usize, constants: &[Value], stack: &mut Vec<Value>, loops: &mut Vec<LoopFrame>) -> Result<usize, Error> {
+        match instr {
+            Instr::Const(index) => stack.push(constants[*index].clone()),
+            Instr::Load(name) => match self.get_ident(name) {
+                Some(var) => stack.push(var.value),
+                None => {
+                    return Err(Error::VarNotFound(VarNotFoundError {
+                        var_name: name.clone(),
+                    }))
+                }
+            },
+            Instr::Define { name, mutable, type_ } => {
+                let value = stack.pop().unwrap();
+                stack.push(self.define(name.clone(), value, *mutable, type_.clone())?);
+            }
+            Instr::Store(name) => {
+                let value = stack.pop().unwrap();
+                stack.push(self.set_var(name.clone(), value)?);
+            }
+            Instr::Update(op, name) => {
+                let value = stack.pop().unwrap();
+                stack.push(match op {
+                    IOp::IAdd => self.iadd(name.clone(), value),
+                    IOp::ISub => self.isub(name.clone(), value),
+                    IOp::IMul => self.imul(name.clone(), value),
+                    IOp::IDiv => self.idiv(name.clone(), value),
+                }?);
+            }
+            Instr::Binary(op) => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                stack.push(self.binary(op, left, right)?);
+            }
+            Instr::Unary(op) => {
+                let value = stack.pop().unwrap();
+                stack.push(match op {
+                    UnaryOp::Neg => value.neg(),
+                    UnaryOp::Not => value.not(),
+                }?);
+            }
+            Instr::Identity(op, index) => {
+                if !matches!(stack.last(), Some(Value::Int(_))) {
+                    let left = stack.pop().unwrap();
+                    stack.push(self.binary(op, left, constants[*index].clone())?);
+                }
+            }
+            Instr::Involution(op) => {
+                let unchanged = match (op, stack.last().unwrap()) {
+                    (UnaryOp::Neg, Value::Int(n)) => *n != i64::MIN,
+                    (UnaryOp::Neg, Value::Float(_)) | (UnaryOp::Not, Value::Bool(_)) => true,
+                    _ => false,
+                };
+                if !unchanged {
+                    let value = stack.pop().unwrap();
+                    stack.push(match op {
+                        UnaryOp::Neg => value.neg()?.neg(),
+                        UnaryOp::Not => value.not()?.not(),
+                    }?);
+                }
+            }
+            Instr::And(target) | Instr::Or(target) => {
+                let short_circuit = matches!(instr, Instr::Or(_));
+                match stack.last().unwrap() {
+                    Value::Bool(b) if *b == short_circuit => return Ok(*target),
+                    Value::Bool(_) => {}
+                    left => {
+                        return Err(Error::TypeMismatch(TypeMismatchError {
+                            expected: Type::Bool,
+                            found: left.get_type(),
+                        }))
+                    }
+                }
+            }
+            Instr::Jump(target) => return Ok(*target),
+            Instr::JumpIfFalse(target) => {
+                if !stack.pop().unwrap().is_truthy() {
+                    return Ok(*target);
+                }
+            }
+            Instr::Pop => {
+                stack.pop();
+            }
+            Instr::PushScope => self.push_scope(),
+            Instr::PopScope => self.pop_scope(),
+            Instr::CheckLength(length) => self.check_length(*length)?,
+            Instr::MakeList(length) => {
+                let items = stack.split_off(stack.len() - length);
+                stack.push(Value::List(items));
+            }
+            Instr::Resolve { name, argc } => match self.get_ident(name).map(|var| var.value) {
+                Some(Value::Function { args, .. }) if *argc < args.len() => {
+                    return Err(Error::FunctionArgumentMismatch(FunctionArgumentMismatchError {
+                        name: name.clone(),
+                        expected: args.len(),
+                        found: *argc,
+                    }))
+                }
+                Some(f @ Value::Function { .. }) => stack.push(f),
+                Some(f) => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::Func,
+                        found: f.get_type(),
+                    }))
+                }
+                None => {
+                    return Err(Error::FunctionNotFound(FunctionNotFoundError {
+                        name: name.clone(),
+                    }))
+                }
+            },
+            Instr::Call(argc) => {
+                let values = stack.split_off(stack.len() - argc);
+                match stack.pop().unwrap() {
+                    Value::Function { func: Function(f, _), args, .. } => {
+                        stack.push(f(call_args(&args, values), self.clone())?);
+                    }
+                    _ => unreachable!("Resolve only pushes functions"),
+                }
+            }
+            Instr::EnterLoop { exit } => loops.push(LoopFrame {
+                exit: *exit,
+                resume: ip + 1,
+                stack: stack.len(),
+                scopes: self.scopes.len(),
+                last: Value::None,
+                items: None,
+            }),
+            Instr::Iterate { exit } => {
+                let items = for_items(stack.pop().unwrap())?;
+                loops.push(LoopFrame {
+                    exit: *exit,
+                    resume: ip + 1,
+                    stack: stack.len(),
+                    scopes: self.scopes.len(),
+                    last: Value::None,
+                    items: Some(items),
+                });
+            }
+            Instr::Next { name, exit } => {
+                match loops.last_mut().and_then(|frame| frame.items.as_mut()).and_then(|items| items.next()) {
+                    Some(item) => self.bind_loop_var(name, item),
+                    None => return Ok(*exit),
+                }
+            }
+            Instr::SetLast => {
+                let value = stack.pop().unwrap();
+                loops.last_mut().unwrap().last = value;
+            }
+            Instr::ExitLoop => {
+                let frame = loops.pop().unwrap();
+                stack.push(frame.last);
+            }
+            Instr::Break => return Err(Error::Break),
+            Instr::Continue => return Err(Error::Continue),
+            Instr::Eval(expr) => stack.push(self.eval_expr(expr.clone())?),
+        }
+        Ok(ip + 1)
+    }
+}
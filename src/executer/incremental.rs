@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+
+use super::*;
+
+/// A program kept statement by statement, so that after an edit only the
+/// statements the edit affects are evaluated again.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    statements: Vec<Expr>,
+    results: Vec<Value>,
+    pending: Vec<bool>,
+}
+
+// The names one statement binds or mutates, and the names it reads.
+#[derive(Default)]
+pub(super) struct Names {
+    pub(super) writes: HashSet<String>,
+    pub(super) reads: HashSet<String>,
+}
+
+impl Program {
+    pub fn parse(src: &str) -> Result<Program, Error> {
+        let statements = parse_statements(src)?;
+        Ok(Program {
+            results: vec![Value::None; statements.len()],
+            pending: vec![true; statements.len()],
+            statements,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    /// The value of each statement as of its last evaluation.
+    pub fn results(&self) -> &[Value] {
+        &self.results
+    }
+
+    /// Replaces the statement at `index` with the single statement in `src`.
+    /// It is marked to run again, together with every later statement that
+    /// uses a name it binds, transitively. A statement that updates a name in
+    /// place, like `x += 1`, also runs the statement that last bound that name
+    /// again first, so that it does not apply on top of its previous run.
+    pub fn edit(&mut self, index: usize, src: &str) -> Result<(), Error> {
+        let mut statements = parse_statements(src)?;
+        if statements.len() != 1 {
+            return Err(Error::Parse(ParseError {
+                message: format!("expected a single statement, found {}", statements.len()),
+            }));
+        }
+        if index >= self.statements.len() {
+            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
+                index: index as i32,
+                name: "program".to_string(),
+            }));
+        }
+        let old = std::mem::replace(&mut self.statements[index], statements.remove(0));
+        let old = names(&old);
+        let mut dirty = old.writes.clone();
+        dirty.extend(names(&self.statements[index]).writes);
+        self.pending[index] = true;
+        let mut from = index;
+        // The replaced statement's updates are part of the current values too.
+        let mut start = old.updates().filter_map(|name| self.binder(name, index)).min();
+        loop {
+            if let Some(binder) = start {
+                self.pending[binder] = true;
+                dirty.extend(names(&self.statements[binder]).writes);
+                from = binder;
+            }
+            self.mark_dependents(from, &mut dirty);
+            start = self.stale_binder(from);
+            if start.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn mark_dependents(&mut self, from: usize, dirty: &mut HashSet<String>) {
+        for i in from + 1..self.statements.len() {
+            let Names { writes, reads } = names(&self.statements[i]);
+            if self.pending[i] || !dirty.is_disjoint(&reads) || !dirty.is_disjoint(&writes) {
+                self.pending[i] = true;
+                dirty.extend(writes);
+            }
+        }
+    }
+
+    // The earliest statement not marked to run that last bound a name some
+    // marked statement at or after `from` updates in place.
+    fn stale_binder(&self, from: usize) -> Option<usize> {
+        (from..self.statements.len())
+            .filter(|&i| self.pending[i])
+            .flat_map(|i| {
+                let names = names(&self.statements[i]);
+                names.updates().filter_map(|name| self.binder(name, i)).collect::<Vec<usize>>()
+            })
+            .filter(|&binder| !self.pending[binder])
+            .min()
+    }
+
+    // The last statement before `before` that binds `name` without reading it.
+    fn binder(&self, name: &str, before: usize) -> Option<usize> {
+        (0..before).rev().find(|&i| {
+            let names = names(&self.statements[i]);
+            names.writes.contains(name) && !names.reads.contains(name)
+        })
+    }
+}
+
+impl Names {
+    fn write(&mut self, name: &str) {
+        self.writes.insert(name.to_string());
+    }
+
+    fn read(&mut self, name: &str) {
+        self.reads.insert(name.to_string());
+    }
+
+    // Names both read and written, as by `x += 1` or `@push('xs', 1)`.
+    fn updates(&self) -> impl Iterator<Item = &String> {
+        self.writes.intersection(&self.reads)
+    }
+}
+
+fn parse_statements(src: &str) -> Result<Vec<Expr>, Error> {
+    let program = crate::tlang::ExprsParser::new()
+        .parse(src)
+        .map_err(|e| Error::Parse(ParseError {
+            message: e.to_string(),
+        }))?;
+    Ok(match program {
+        Expr::Block { body } => body,
+        expr => vec![expr],
+    })
+}
+
+pub(super) fn names(expr: &Expr) -> Names {
+    let mut names = Names::default();
+    collect(expr, &mut names);
+    names
+}
+
+// Conservative: names bound in nested blocks or as parameters still count,
+// which can only cause extra statements to run again.
+fn collect(expr: &Expr, names: &mut Names) {
+    match expr {
+        Expr::Ident { ident } => {
+            names.read(ident);
+        }
+        Expr::Assign { name, value, .. } => {
+            names.write(name);
+            collect(value, names);
+        }
+        Expr::AssignTuple { names: bound, value, .. } => {
+            for name in bound {
+                names.write(name);
+            }
+            collect(value, names);
+        }
+        Expr::IOp { name, value, .. } | Expr::SetVar { name, value } | Expr::SetAttr { name, value, .. } => {
+            names.write(name);
+            names.read(name);
+            collect(value, names);
+        }
+        Expr::SetIndex { name, index, value } => {
+            names.write(name);
+            names.read(name);
+            collect(index, names);
+            collect(value, names);
+        }
+        Expr::Swap { a, b } => {
+            for name in [a, b] {
+                names.write(name);
+                names.read(name);
+            }
+        }
+        Expr::For { name, iter, body } => {
+            if let Expr::Ident { ident } = &**name {
+                names.write(ident);
+            }
+            collect(iter, names);
+            collect(body, names);
+        }
+        Expr::FunDef { name, body, .. } => {
+            names.write(name);
+            collect(body, names);
+        }
+        Expr::Impl { name_struct, body, .. } => {
+            names.write(name_struct);
+            names.read(name_struct);
+            collect(body, names);
+        }
+        Expr::StructDef { name, .. } | Expr::Enum { name, .. } => names.write(name),
+        Expr::Call { name, args } => {
+            names.read(name);
+            // `push` and `pop` update the list variable named by their first argument.
+            if let (true, Some(Expr::Literal { value: Literal::String(list) })) = (name == "push" || name == "pop", args.first()) {
+                names.write(list);
+                names.read(list);
+            }
+            for arg in args {
+                collect(arg, names);
+            }
+        }
+        Expr::CallStruct { name, base, args } => {
+            names.read(name);
+            if let Some(base) = base {
+                collect(base, names);
+            }
+            for (_, value) in args {
+                collect(value, names);
+            }
+        }
+        Expr::GetAttr { name, .. } | Expr::EnumCall { name, .. } => {
+            names.read(name);
+        }
+        Expr::Lambda { body, .. } => collect(body, names),
+        Expr::Apply { func, args } => {
+            collect(func, names);
+            for arg in args {
+                collect(arg, names);
+            }
+        }
+        Expr::GetFunc { name, args, .. } => {
+            names.read(name);
+            for arg in args {
+                collect(arg, names);
+            }
+        }
+        Expr::IfThen { cond, then } => {
+            collect(cond, names);
+            collect(then, names);
+        }
+        Expr::IfThenElse { cond, then, else_ } => {
+            collect(cond, names);
+            collect(then, names);
+            collect(else_, names);
+        }
+        Expr::While { cond, body } => {
+            collect(cond, names);
+            collect(body, names);
+        }
+        Expr::BinOp { left, right, .. } | Expr::CustomOp { left, right, .. } => {
+            collect(left, names);
+            collect(right, names);
+        }
+        Expr::Index { name: left, index: right } | Expr::Range { start: left, end: right } => {
+            collect(left, names);
+            collect(right, names);
+        }
+        Expr::UnaryOp { value, .. }
+        | Expr::To { value, .. }
+        | Expr::Return { value }
+        | Expr::Spread { value } => collect(value, names),
+        Expr::With { name, value, body } => {
+            names.write(name);
+            collect(value, names);
+            collect(body, names);
+        }
+        Expr::TryCatch { body, handler, .. } => {
+            collect(body, names);
+            collect(handler, names);
+        }
+        Expr::Block { body }
+        | Expr::List { elems: body }
+        | Expr::Tuple { elems: body }
+        | Expr::AssignMany { assigns: body } => {
+            for expr in body {
+                collect(expr, names);
+            }
+        }
+        Expr::Match { value, cases } => {
+            collect(value, names);
+            for (pattern, body) in cases {
+                collect(pattern, names);
+                collect(body, names);
+            }
+        }
+        Expr::Map { entries } => {
+            for (key, value) in entries {
+                collect(key, names);
+                collect(value, names);
+            }
+        }
+        Expr::Literal { .. } | Expr::Break | Expr::Continue | Expr::Empty => {}
+    }
+}
+
+impl Vm {
+    /// Evaluates the statements of `program` that have not run since they
+    /// were parsed or affected by an edit, in order, in the current scope.
+    /// Returns the value of every statement.
+    pub fn run_program(&mut self, program: &mut Program) -> Result<Vec<Value>, Error> {
+        for i in 0..program.statements.len() {
+            if program.pending[i] {
+                program.results[i] = self.eval_expr(program.statements[i].clone())?;
+                program.pending[i] = false;
+            }
+        }
+        Ok(program.results.clone())
+    }
+}
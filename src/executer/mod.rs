@@ -1,11 +1,23 @@
 
 pub(crate) mod value;
+pub(crate) mod bytecode;
+pub(crate) mod incremental;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::rc::Rc;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::BufRead;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
 use crate::std_t::Builtin;
 use crate::tree::Expr;
 use crate::tree::Op;
 use crate::tree::IOp;
+use crate::tree::UnaryOp;
 use crate::tree::Literal;
 use crate::tree::Type as TypeAst;
 use crate::errors::*;
@@ -14,60 +26,548 @@ use self::value::Function;
 use self::value::Ident;
 use self::value::Var;
 use self::value::Type;
+use self::value::MapKey;
+use self::value::DisplayFormat;
 use crate::std_t::BuiltinFunction;
 
 
-fn function(body: Expr) -> Function {
-    let body_clone = body.clone();
-    Function(Rc::new(move |args: HashMap<String, Var>,  vm: Vm| -> Result<Value, Error> {
-        let mut vm = vm.clone();
-        for i in args.iter() {
-            vm.set_ident(Ident(i.0.clone()), i.1.clone());
+fn function(body: Expr, env: Vec<Rc<RefCell<Scope>>>) -> Function {
+    let source = Rc::new(body.clone());
+    Function(Rc::new(move |args: HashMap<String, Var>, mut vm: Vm| -> Result<Value, Error> {
+        vm.depth += 1;
+        if let Some(limit) = vm.recursion_limit.filter(|limit| vm.depth > *limit) {
+            return Err(Error::RecursionLimitExceeded(RecursionLimitExceededError { limit }));
         }
-        vm.eval_expr(body_clone.clone())
-        
-    }))
+        vm.scopes = env.clone();
+        vm.push_scope();
+        for (name, var) in args {
+            vm.set_ident(Ident(name), var);
+        }
+        let result = match vm.eval_expr(body.clone()) {
+            Err(Error::Return(value)) => Ok(value),
+            result => result,
+        };
+        vm.pop_scope();
+        result
+    }), Some(source))
+}
+
+fn param_names(args: &[Expr]) -> Result<Vec<String>, Error> {
+    args.iter()
+        .map(|arg| match arg {
+            Expr::Ident { ident } => Ok(ident.clone()),
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::None,
+                found: Type::None,
+            })),
+        })
+        .collect()
+}
+
+// Arguments past the declared parameters are keyed by their position.
+pub(crate) fn call_args(params: &[String], values: Vec<Value>) -> HashMap<String, Var> {
+    let mut dict_args = HashMap::new();
+    for (i, value) in values.into_iter().enumerate() {
+        let arg = match params.get(i) {
+            Some(arg) => arg.clone(),
+            None => i.to_string(),
+        };
+        dict_args.insert(arg, Var {
+            type_: value.get_type(),
+            value,
+            mutable: false,
+        });
+    }
+    dict_args
+}
+
+/// How many values `range_steps` yields for `r`, counting down when `start`
+/// is past `end`.
+pub(crate) fn range_len(r: &std::ops::Range<isize>) -> usize {
+    r.start.abs_diff(r.end)
+}
+
+// A range whose start exceeds its end counts down, still excluding the end.
+fn range_steps(r: std::ops::Range<isize>) -> Box<dyn Iterator<Item = isize>> {
+    if r.start > r.end {
+        Box::new((r.end + 1..=r.start).rev())
+    } else {
+        Box::new(r)
+    }
+}
+
+// The values a `for` loop binds in turn, shared by both evaluators.
+fn for_items(iter: Value) -> Result<Box<dyn Iterator<Item = Value>>, Error> {
+    match iter {
+        Value::List(l) => Ok(Box::new(l.into_iter())),
+        Value::Range(r) => Ok(Box::new(range_steps(r).map(|i| Value::Int(i as i64)))),
+        iter => Err(Error::TypeMismatch(TypeMismatchError {
+            expected: Type::List,
+            found: iter.get_type(),
+        })),
+    }
+}
+
+type Scope = HashMap<Ident, Var>;
+
+#[derive(Clone)]
+pub struct Sink(pub Rc<RefCell<dyn Write>>);
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sink")
+    }
+}
+
+#[derive(Clone)]
+pub struct Source(pub Rc<RefCell<dyn BufRead>>);
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Source")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProfileEntry {
+    pub count: u64,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vm {
+    scopes: Vec<Rc<RefCell<Scope>>>,
+    out: Sink,
+    err: Sink,
+    input: Source,
+    separator: Rc<str>,
+    pool: Option<Rc<RefCell<Vec<Scope>>>>,
+    max_length: usize,
+    shadowing: bool,
+    profile: Option<Rc<RefCell<HashMap<&'static str, ProfileEntry>>>>,
+    operators: Rc<HashMap<String, String>>,
+    builtins: Rc<HashMap<String, Value>>,
+    display_format: DisplayFormat,
+    grow_lists: bool,
+    declared: Option<Rc<RefCell<Vec<HashSet<String>>>>>,
+    depth: usize,
+    recursion_limit: Option<usize>,
+    steps: Rc<Cell<u64>>,
+    step_limit: Option<u64>,
+    deadline: Option<(Instant, Duration)>,
 }
 
+/// The settings and limits a `pmap` worker thread inherits from the
+/// interpreter that started it, since a `Vm` holds `Rc`s and cannot cross
+/// threads itself.
 #[derive(Debug, Clone)]
-pub struct Vm(
-    std::collections::HashMap<Ident, Var>
-);
+pub(crate) struct WorkerConfig {
+    max_length: usize,
+    operators: HashMap<String, String>,
+    display_format: DisplayFormat,
+    depth: usize,
+    recursion_limit: Option<usize>,
+    step_limit: Option<u64>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+const MAX_POOLED_SCOPES: usize = 64;
+const DEFAULT_MAX_LENGTH: usize = 1 << 26;
+// Reading the clock on every expression would dominate short evaluations.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
 
 impl Vm {
     pub fn new() -> Self {
-        let mut vm = Vm(HashMap::new());
+        Vm::new_with_capacity(0)
+    }
+
+    pub fn new_with_capacity(capacity: usize) -> Self {
+        let mut vm = Vm {
+            scopes: vec![Rc::new(RefCell::new(HashMap::with_capacity(capacity)))],
+            out: Sink(Rc::new(RefCell::new(std::io::stdout()))),
+            err: Sink(Rc::new(RefCell::new(std::io::stderr()))),
+            input: Source(Rc::new(RefCell::new(std::io::BufReader::new(std::io::stdin())))),
+            separator: Rc::from(" "),
+            pool: None,
+            max_length: DEFAULT_MAX_LENGTH,
+            shadowing: true,
+            profile: None,
+            operators: Rc::new(HashMap::new()),
+            builtins: Rc::new(HashMap::new()),
+            display_format: DisplayFormat::default(),
+            grow_lists: false,
+            declared: None,
+            depth: 0,
+            recursion_limit: None,
+            steps: Rc::new(Cell::new(0)),
+            step_limit: None,
+            deadline: None,
+        };
         vm.use_builtin_function();
         vm
     }
 
+    pub fn set_max_length(&mut self, max_length: usize) -> &mut Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Bounds how deeply function calls may nest. Unlimited by default.
+    pub fn set_recursion_limit(&mut self, limit: usize) -> &mut Self {
+        self.recursion_limit = Some(limit);
+        self
+    }
+
+    /// Bounds how many expressions may be evaluated from now on, counting
+    /// those inside function calls. Unlimited by default.
+    pub fn set_step_limit(&mut self, limit: u64) -> &mut Self {
+        self.steps.set(0);
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Like `eval_many`, but fails with a timeout error once `timeout` has
+    /// elapsed. The clock is read every `DEADLINE_CHECK_INTERVAL` steps, so
+    /// a builtin that blocks is not interrupted.
+    pub fn eval_with_timeout(&mut self, src: &str, timeout: Duration) -> Result<Vec<Value>, Error> {
+        let previous = self.deadline.replace((Instant::now() + timeout, timeout));
+        let result = self.eval_many(src);
+        self.deadline = previous;
+        result
+    }
+
+    pub fn check_length(&self, length: usize) -> Result<(), Error> {
+        if length > self.max_length {
+            return Err(Error::MaxLengthExceeded(MaxLengthExceededError {
+                limit: self.max_length,
+                length,
+            }));
+        }
+        Ok(())
+    }
+
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled {
+            Some(Rc::new(RefCell::new(HashMap::new())))
+        } else {
+            None
+        };
+    }
+
+    /// Count and inclusive time spent per expression kind, slowest first.
+    pub fn profile_report(&self) -> Vec<(&'static str, ProfileEntry)> {
+        let mut report = match &self.profile {
+            Some(profile) => profile.borrow().iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        report.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+        report
+    }
+
+    /// Whether assigning past the end of a list grows it, filling the gap
+    /// with `None`, rather than failing with an out-of-bounds error.
+    pub fn set_list_growth(&mut self, enabled: bool) {
+        self.grow_lists = enabled;
+    }
+
+    /// Warns on the error output when a function being defined reads a name
+    /// that is not a parameter, not bound in its body, not visible where it
+    /// is defined and not bound anywhere in an enclosing block, so functions
+    /// may still call ones defined after them.
+    pub fn set_name_checks(&mut self, enabled: bool) {
+        self.declared = if enabled {
+            Some(Rc::new(RefCell::new(Vec::new())))
+        } else {
+            None
+        };
+    }
+
+    fn check_names(&self, name: &str, params: &[String], body: &Expr) {
+        let declared = match &self.declared {
+            Some(declared) => declared.borrow(),
+            None => return,
+        };
+        let names = incremental::names(body);
+        let mut unknown = names.reads.iter()
+            .filter(|n| !names.writes.contains(*n) && !params.contains(n) && *n != name)
+            .filter(|n| !declared.iter().any(|block| block.contains(*n)) && self.get_ident(n).is_none())
+            .collect::<Vec<&String>>();
+        unknown.sort();
+        for n in unknown {
+            self.write_error(&format!("warning: function {} uses undefined name {}\n", name, n));
+        }
+    }
+
+    pub fn set_shadowing(&mut self, enabled: bool) {
+        self.shadowing = enabled;
+    }
+
+    pub fn set_scope_pooling(&mut self, enabled: bool) {
+        self.pool = if enabled {
+            Some(Rc::new(RefCell::new(Vec::new())))
+        } else {
+            None
+        };
+    }
+
+    pub fn set_output(&mut self, out: Rc<RefCell<dyn Write>>) -> &mut Self {
+        self.out = Sink(out);
+        self
+    }
+
+    pub fn set_error_output(&mut self, err: Rc<RefCell<dyn Write>>) -> &mut Self {
+        self.err = Sink(err);
+        self
+    }
+
+    /// Where `read` and `input` take their lines from, stdin by default.
+    pub fn set_input(&mut self, input: Rc<RefCell<dyn BufRead>>) -> &mut Self {
+        self.input = Source(input);
+        self
+    }
+
+    /// The next line of input without its line ending, or `None` at the end
+    /// of the input.
+    pub fn read_line(&self) -> Option<String> {
+        let mut line = String::new();
+        match self.input.0.borrow_mut().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Some(line)
+            }
+        }
+    }
+
+    pub fn set_separator(&mut self, separator: &str) {
+        self.separator = Rc::from(separator);
+    }
+
+    pub fn separator(&self) -> &str {
+        &self.separator
+    }
+
+    /// Groups the integer digits of printed numbers in threes, as in `1,234,567`.
+    pub fn set_thousands_separator(&mut self, enabled: bool) {
+        self.display_format.thousands_separator = enabled;
+    }
+
+    /// Prints floats with exactly `digits` decimals, or unrounded with `None`.
+    pub fn set_precision(&mut self, digits: Option<usize>) {
+        self.display_format.precision = digits;
+    }
+
+    /// Prints a `None` argument as nothing instead of `None`.
+    pub fn set_empty_none(&mut self, enabled: bool) {
+        self.display_format.empty_none = enabled;
+    }
+
+    pub fn display_format(&self) -> DisplayFormat {
+        self.display_format
+    }
+
+    pub fn write_output(&self, s: &str) {
+        let mut out = self.out.0.borrow_mut();
+        let _ = out.write_all(s.as_bytes());
+        let _ = out.flush();
+    }
+
+    pub fn write_error(&self, s: &str) {
+        let mut err = self.err.0.borrow_mut();
+        let _ = err.write_all(s.as_bytes());
+        let _ = err.flush();
+    }
+
     pub fn use_builtin_function(&mut self) {
         let map = BuiltinFunction::build();
+        let mut builtins = HashMap::new();
         for i in map.iter() {
+            let value = Value::Function { name: i.0.clone(), func: Function(i.1.0.clone(), None), args: i.1.1.clone()};
+            builtins.insert(i.0.clone(), value.clone());
             self.set_ident(Ident(i.0.clone()), Var {
-                value: Value::Function { name: i.0.clone(), func: Function(i.1.0.clone()), args: i.1.1.clone()},
+                value,
                 type_: Type::Func,
                 mutable: false,
             });
         }
-    }    
+        self.builtins = Rc::new(builtins);
+    }
+
+    /// Whether `name` still resolves to the builtin of that name, rather
+    /// than to something defined over it.
+    pub(crate) fn is_builtin(&self, name: &str) -> bool {
+        match (self.get_ident(name), self.builtins.get(name)) {
+            (Some(var), Some(builtin)) => var.value == *builtin,
+            _ => false,
+        }
+    }
+
+    /// Whether a worker interpreter holding only the builtins would run
+    /// `expr` as this one does, without calling any builtin in `excluded`.
+    pub(crate) fn runs_like_a_worker(&self, expr: &Expr, excluded: &[&str]) -> bool {
+        let mut reads = incremental::names(expr).reads;
+        reads.extend(self.operators.values().cloned());
+        self.builtins
+            .keys()
+            .filter(|name| reads.contains(*name))
+            .all(|name| !excluded.contains(&name.as_str()) && self.is_builtin(name))
+    }
+
+    pub fn define_struct(&mut self, name: &str, fields: &[&str]) {
+        self.set_ident(Ident(name.to_string()), Var {
+            value: Value::DefStruct {
+                name: name.to_string(),
+                fields: fields.iter().map(|f| Ident(f.to_string())).collect(),
+                function: HashMap::new(),
+            },
+            type_: Type::Struct(name.to_string()),
+            mutable: false,
+        });
+    }
+
+    pub fn define_function<F>(&mut self, name: &str, args: &[&str], f: F)
+    where
+        F: Fn(HashMap<String, Var>, Vm) -> Result<Value, Error> + 'static,
+    {
+        self.set_ident(Ident(name.to_string()), Var {
+            value: Value::Function {
+                name: name.to_string(),
+                func: Function(Rc::new(f), None),
+                args: args.iter().map(|a| a.to_string()).collect(),
+            },
+            type_: Type::Func,
+            mutable: false,
+        });
+    }
+
+    /// Makes `a `op` b` call the function `function` with `a` and `b`.
+    pub fn define_operator(&mut self, op: &str, function: &str) {
+        Rc::make_mut(&mut self.operators).insert(op.to_string(), function.to_string());
+    }
+
+    /// Calls a function value with already evaluated arguments, as `@f(...)` would.
+    pub fn call_value(&self, f: &Value, values: Vec<Value>) -> Result<Value, Error> {
+        match f {
+            Value::Function { name, func: Function(func, _), args } => {
+                if values.len() < args.len() {
+                    return Err(Error::FunctionArgumentMismatch(FunctionArgumentMismatchError {
+                        name: name.clone(),
+                        expected: args.len(),
+                        found: values.len(),
+                    }));
+                }
+                func(call_args(args, values), self.clone())
+            }
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Func,
+                found: f.get_type(),
+            })),
+        }
+    }
+
+    // One step is an evaluated expression or an executed bytecode instruction.
+    fn count_step(&self) -> Result<(), Error> {
+        if self.step_limit.is_some() || self.deadline.is_some() {
+            let steps = self.steps.get() + 1;
+            if let Some(limit) = self.step_limit {
+                if steps > limit {
+                    return Err(Error::StepLimitExceeded(StepLimitExceededError { limit }));
+                }
+            }
+            if let Some((deadline, timeout)) = self.deadline {
+                if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                    return Err(Error::Timeout(TimeoutError { timeout }));
+                }
+            }
+            self.steps.set(steps);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn worker_config(&self) -> WorkerConfig {
+        WorkerConfig {
+            max_length: self.max_length,
+            operators: (*self.operators).clone(),
+            display_format: self.display_format,
+            depth: self.depth,
+            recursion_limit: self.recursion_limit,
+            step_limit: self.step_limit.map(|limit| limit.saturating_sub(self.steps.get())),
+            deadline: self.deadline,
+        }
+    }
+
+    /// A fresh interpreter with only the builtins, set up as `config` says.
+    /// Its step limit is whatever budget the parent had left.
+    pub(crate) fn with_worker_config(config: &WorkerConfig) -> Vm {
+        let mut vm = Vm::new();
+        vm.max_length = config.max_length;
+        vm.operators = Rc::new(config.operators.clone());
+        vm.display_format = config.display_format;
+        vm.depth = config.depth;
+        vm.recursion_limit = config.recursion_limit;
+        vm.step_limit = config.step_limit;
+        vm.deadline = config.deadline;
+        vm
+    }
+
+    pub(crate) fn steps_taken(&self) -> u64 {
+        self.steps.get()
+    }
+
+    /// Counts `steps` taken elsewhere, such as on a worker thread, against
+    /// this interpreter's step limit.
+    pub(crate) fn charge_steps(&self, steps: u64) -> Result<(), Error> {
+        let steps = self.steps.get().saturating_add(steps);
+        self.steps.set(steps);
+        match self.step_limit {
+            Some(limit) if steps > limit => Err(Error::StepLimitExceeded(StepLimitExceededError { limit })),
+            _ => Ok(()),
+        }
+    }
+
     pub fn eval_expr(&mut self, expr: Expr) -> Result<Value, Error> {
+        self.count_step()?;
+        let profile = match &self.profile {
+            Some(profile) => profile.clone(),
+            None => return self.eval_node(expr),
+        };
+        let kind = expr.kind();
+        let start = Instant::now();
+        let result = self.eval_node(expr);
+        let mut profile = profile.borrow_mut();
+        let entry = profile.entry(kind).or_default();
+        entry.count += 1;
+        entry.total += start.elapsed();
+        result
+    }
+
+    // Arms with more than a few locals get methods of their own: an unoptimized
+    // build reserves room for every arm's temporaries in this frame, and it is
+    // on the stack once per nested expression.
+    fn eval_node(&mut self, expr: Expr) -> Result<Value, Error> {
         match expr {
             Expr::Empty => Ok(Value::None),
-            Expr::Block { body } => {
-                let mut last = Value::None;
-                for expr in body {
-                    last = self.eval_expr(expr)?;
-                }
-                Ok(last)
-            },
+            Expr::Break => Err(Error::Break),
+            Expr::Continue => Err(Error::Continue),
+            Expr::Return { value } => Err(Error::Return(self.eval_expr(*value)?)),
+            Expr::Spread { .. } => Err(Error::InvalidArgument(InvalidArgumentError {
+                name: "...".to_string(),
+                reason: "spread is only allowed inside a list literal".to_string(),
+            })),
+            Expr::Block { body } => self.eval_scoped_block(body),
             Expr::Literal { value } => Ok(match value {
-                Literal::Number(n) => Value::Number(n),
+                Literal::Int(n) => Value::Int(n),
+                Literal::Float(n) => Value::Float(n),
                 Literal::String(s) => Value::String(s),
                 Literal::Bool(b) => Value::Bool(b),
             }),
             Expr::Ident { ref ident } => {
-                match self.get_ident(Ident(ident.clone())) {
+                match self.get_ident(ident) {
                     Some(var) => Ok(var.clone().value),
                     None => {
                         Err(Error::VarNotFound(VarNotFoundError {
@@ -76,821 +576,1011 @@ impl Vm {
                     },
                 }
             },
+            Expr::BinOp { op: Op::And, left, right } => self.eval_and(*left, *right),
+            Expr::BinOp { op: Op::Or, left, right } => self.eval_or(*left, *right),
             Expr::BinOp { op, left, right } => {
                 let left = self.eval_expr(*left)?;
 
                 let right = self.eval_expr(*right)?;
-                Ok(match op {
-                    Op::Add => left.add(&right)?,
-                    Op::Sub => left.sub(&right)?,
-                    Op::Mul => left.mul(&right)?,
-                    Op::Div => left.div(&right)?,
-                    Op::Mod => left.modulo(&right)?,
-                    Op::Eq => left.eq(&right)?,
-                    Op::Neq => left.neq(&right)?,
-                    Op::Gt => left.gt(&right)?,
-                    Op::Lt => left.lt(&right)?,
-                    Op::Ge => left.ge(&right)?,
-                    Op::Le => left.le(&right)?,
-                    Op::And => left.and(&right)?,
-                    Op::Or => left.or(&right)?,
-                })
+                self.binary(&op, left, right)
+            },
+            Expr::UnaryOp { op, value } => {
+                let value = self.eval_expr(*value)?;
+                match op {
+                    UnaryOp::Neg => value.neg(),
+                    UnaryOp::Not => value.not(),
+                }
             },
             Expr::IfThen { cond, then } => {
                 let v = self.eval_expr(*cond)?;
-                if let Value::Bool(c) = v {
-                    if c {
-                        Ok(self.eval_expr(*then)?)
-                    } else {
-                        Ok(Value::None)
-                    }
+                if v.is_truthy() {
+                    Ok(self.eval_expr(*then)?)
                 } else {
-                    Err(Error::TypeMismatch(TypeMismatchError {
-                        expected: Type::Bool,
-                        found: v.get_type(),
-                    }))
+                    Ok(Value::None)
                 }
             }
             Expr::IfThenElse { cond, then, else_ } => {
                 let v = self.eval_expr(*cond)?;
-                if let Value::Bool(n) = v {
-                    if n {
-                        self.eval_expr(*then)
-                    } else {
-                        self.eval_expr(*else_)
-                    }
+                if v.is_truthy() {
+                    self.eval_expr(*then)
                 } else {
-                    Err(Error::TypeMismatch(TypeMismatchError {
-                        expected: Type::Bool,
-                        found: v.get_type(),
-                    }))
+                    self.eval_expr(*else_)
                 }
             },
             Expr::Assign { name, value, mutable , type_ } => {
                 let value_evaluate = self.eval_expr(*value)?;
-                if self.get_ident(Ident(name.clone())).is_some() {
-                    return Err(Error::VarAlreadyDefined(VarAlreadyDefinedError {
-                        var_name: name,
-                    }));
-                }
-                match type_ {
-                    Some(type_) => {
-                        if value_evaluate.get_type() != type_ {
-                            return Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: type_,
-                                found: value_evaluate.get_type(),
-                            }));
-                        }
-                    },
-                    None => {},
-                }
-
-                self.set_ident(Ident(name), Var {
-                    value: value_evaluate.clone(),
-                    type_: match value_evaluate {
-                        Value::Number(_) => Type::Int,
-                        Value::String(_) => Type::String,
-                        Value::Bool(_) => Type::Bool,
-                        Value::Function { .. } => Type::Func,
-                        Value::DefStruct { name, fields, function } => Type::Struct(name),
-                        Value::CallStruct { name, fields } => Type::FieldStruct(name),
-                        Value::List(_) => Type::List,
-                        Value::Range(_) => Type::Range,
-                        Value::Enum { variants } => Type::Enum,
-                        Value::EnumCall { name, field } => Type::FieldEnum(name),
-                        Value::None => Type::None,
-                    },
-                    mutable,
-                });
-                Ok(Value::None)
-            }
-            Expr::While { ref cond, ref body } => {
-                while self.eval_expr(*cond.clone())? == Value::Bool(true) {
-                    self.eval_expr(*body.clone())?;
-                }
-                Ok(Value::None)
+                self.define(name, value_evaluate, mutable, type_)
             }
-            Expr::For {
-                ref name,
-                ref iter,
-                ref body,
-            } => {
-                let name_str = match *name.clone() {
-                    Expr::Ident { ident } => ident,
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::String,
-                            found: Type::None,
-                        }))
-                    }
-                };
-
-                let iter = self.eval_expr(*iter.clone())?;
-                match iter {
-                    Value::List(ref l) => {
-                        let mut last = Value::None;
-                        for item in l {
-                            self.set_ident(Ident(name_str.clone()), Var{
-                                value: item.clone(),
-                                type_: Type::Int,
-                                mutable: true
-                            });
-                            last = self.eval_expr(*body.clone())?;
-                        }
-                        Ok(last)
-                    }
-                    Value::Range(r) => {
-                        let mut last = Value::None;
-                        for i in r {
-                            self.set_ident(Ident(name_str.clone()), Var {
-                                value: Value::Number(i as f64),
-                                type_: Type::Int,
-                                mutable: true,
-                            });
-                            last = self.eval_expr(*body.clone())?;
-                        }
-                        Ok(last)
-                    }
-                    _ => Err(Error::TypeMismatch(TypeMismatchError {
-                        expected: Type::List,
-                        found: iter.get_type(),
-                    })),
-                }
-            },
-            Expr::FunDef {
-                ref name,
-                ref args,
-                ref body,
-            } => {
-                
-                let mut args_vec = Vec::new();
-                for arg in args {
-                    let arg_name = match arg {
-                        Expr::Ident { ref ident } => ident.clone(),
-                        _ => {
-                            return Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::None,
-                                found: Type::None,
-                            }))
-                        }
-                    };
-                    args_vec.push(arg_name);
-                }
-                
-                self.set_ident(
-                    Ident(name.clone()),
-                    Var {
-                        value: Value::Function { name: name.clone(), func: function(*body.clone()), args: args_vec.clone() },
-                        type_: Type::Func,
-                        mutable: false,
-                    },
-                );
-                Ok(Value::Function { name: name.clone(), func:  function(*body.clone()), args: args_vec })
-            },
-            Expr::Call {
-                ref name, ref args, ..
-            } => {
-                
-                let copy_self = self.clone();
-                match copy_self.get_ident(Ident(name.clone())) {
-                    Some(f) => match f.clone() {
-                        Var{value: Value::Function {
-                            func,
-                            args: a,
-                            ..
-                        }, ..} => {
-                            let mut dict_args = HashMap::new();
-                            for (i, arg) in a.iter().enumerate() {
-                                let arg_value = args[i].clone();
-                                let value = self.eval_expr(arg_value)?;
-                                dict_args.insert(arg.clone(), Var {
-                                    value: value.clone(),
-                                    type_: value.get_type(),
-                                    mutable: false,
-                                });
-                            }
-                            
-                            let Function(f) = func;
-                            f(dict_args, self.clone())
-                        },
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Func,
-                            found: f.value.get_type(),
-                        })),
-                    },
-                    None => Err(Error::FunctionNotFound(FunctionNotFoundError {
-                        name: name.clone(),
-                    })),
-                }
-            },
-            Expr::List { ref elems } => {
-                let mut list = Vec::new();
-                for elem in elems {
-                    list.push(self.eval_expr(elem.clone())?);
-                }
-                Ok(Value::List(list))
-            }
-            Expr::Index {
-                ref name,
-                ref index,
-            } => {
-                let real_name = match **name {
-                    Expr::Ident { ref ident } => ident.clone(),
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::None,
-                            found: Type::None,
-                        }))
-                    }
-                };
-                let copy_vm = self.clone();
-                let list = match copy_vm.get_ident(Ident(real_name.clone())) {
-                    Some(Var{value: Value::List(list), ..}) => list,
-                    None => {
-                        return Err(Error::VarNotFound(VarNotFoundError {
-                            var_name: real_name,
-                        }))
-                    }
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::List,
-                            found: self.get_ident(Ident(real_name)).unwrap().value.get_type(),
-                        }))
-                    }
-                };
-
-                let index = self.eval_expr(*index.clone())?;
-                match index {
-                    Value::Number(num) => {
-                        if num < 0.0 {
-                            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
-                                index: num as i32,
-                                name: real_name,
-                            }));
-                        }
-                        if num as usize >= list.len() {
-                            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
-                                index: num as i32,
-                                name: real_name,
-                            }));
-                        }
-                        Ok(list[num as usize].clone())
-                    }
-                    Value::Range(r) => {
-                        if r.start >= list.len() as isize {
-                            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
-                                index: r.start as i32,
-                                name: real_name,
-                            }));
-                        }
-
-                        if r.end > list.len() as isize {
-                            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
-                                index: r.end as i32,
-                                name: real_name,
-                            }));
-                        }
-
-                        Ok(Value::List(list[r.start as usize..r.end as usize].to_vec()))
-                    }
-                    _ => Err(Error::TypeMismatch(TypeMismatchError {
-                        expected: Type::Int,
-                        found: index.get_type(),
-                    })),
+            Expr::AssignTuple { names, value, mutable } => self.eval_assign_tuple(names, *value, mutable),
+            Expr::AssignMany { assigns } => {
+                let mut last = Value::None;
+                for assign in assigns {
+                    last = self.eval_expr(assign)?;
                 }
+                Ok(last)
             }
+            Expr::While { cond, body } => self.eval_while(&cond, &body),
+            Expr::For { name, iter, body } => self.eval_for_loop(*name, *iter, &body),
+            Expr::FunDef { name, args, body } => self.eval_fun_def(&name, &args, &body),
+            Expr::Lambda { args, body } => self.eval_lambda(&args, *body),
+            Expr::Apply { func, args } => self.eval_apply(*func, args),
+            Expr::Call { name, args } => self.eval_call(&name, &args),
+            Expr::List { elems } => self.eval_list(&elems),
+            Expr::Tuple { elems } => self.eval_tuple(&elems),
+            Expr::Map { entries } => self.eval_map(entries),
+            Expr::Index { name, index } => self.eval_index(&name, &index),
             Expr::Range { ref start, ref end } => {
-                let start = self.eval_expr(*start.clone())?;
-                let end = self.eval_expr(*end.clone())?;
-                let start = match start {
-                    Value::Number(n) => n,
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: start.get_type(),
-                        }))
-                    }
-                };
-                let end = match end {
-                    Value::Number(n) => n,
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: end.get_type(),
-                        }))
-                    }
-                };
+                let start = self.eval_expr(*start.clone())?.as_int()?;
+                let end = self.eval_expr(*end.clone())?.as_int()?;
 
                 Ok(Value::Range(start as isize..end as isize))
             },
-            Expr::StructDef {
-                ref name,
-                ref fields,
-            } => {
-                let mut f = Vec::new();
-                
-                for field in fields {
-                    match field {
-                        Expr::Ident { ref ident } => f.push(ident.clone()),
-                        _ => {
-                            return Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::None,
-                                found: Type::None,
-                            }))
-                        } 
-                    }
-                    
-                }
-                let mut nf = Vec::new();
-                for field in fields {
-                    nf.push(match field {
-                        Expr::Ident { ident } => Ident(ident.clone()),
-                        _ => {
-                            return Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::None,
-                                found: Type::None,
-                            }))
-                        }
-                    });
+            Expr::StructDef { name, fields } => self.eval_struct_def(&name, &fields),
+            Expr::CallStruct { name, base, args } => self.eval_call_struct(&name, base.as_deref(), &args),
+            Expr::GetAttr { name, attr, optional } => self.eval_get_attr(name, attr, optional),
+            Expr::Impl { name_struct, name_method, args, body } => self.eval_impl(&name_struct, &name_method, args, *body),
+            Expr::GetFunc { name, func, args, optional } => self.eval_method_call(name, func, args, optional),
+            Expr::SetVar { name, value } => {
+                let v = self.eval_expr(*value.clone())?;
+                self.set_var(name, v)
+            },
+            Expr::SetAttr { name, attr, value } => self.eval_set_attr(name, attr, *value),
+            Expr::SetIndex { name, index, value } => self.eval_set_index(name, *index, *value),
+            Expr::CustomOp { op, left, right } => self.eval_custom_op(op, *left, *right),
+            Expr::Swap { a, b } => self.eval_swap(a, b),
+            Expr::IOp { op, name, value } => {
+                let v = self.eval_expr(*value.clone())?;
+                match op {
+                    IOp::IAdd => self.iadd(name, v),
+                    IOp::ISub => self.isub(name, v),
+                    IOp::IMul => self.imul(name, v),
+                    IOp::IDiv => self.idiv(name, v)
                 }
-                self.set_ident(Ident(name.clone()), Var {
-                    value:Value::DefStruct {
-                        name: name.clone(),
-                        fields: nf,
-                        function: HashMap::new()
-                    },
-                    type_: Type::Struct(name.clone()),
-                    mutable: false,
+            },
+            Expr::Match { value, cases } => self.eval_match(*value, cases),
+            Expr::Enum { name, fields } => {
+                self.set_ident(Ident(name), Var {
+                    value: Value::Enum { variants: fields },
+                    type_: Type::Enum,
+                    mutable: false
                 });
                 Ok(Value::None)
+            }
+            Expr::EnumCall { name, field } => self.eval_enum_call(name, field),
+            Expr::To { value, to } => {
+                let v = self.eval_expr(*value.clone())?;
+                v.cast(&to)
             },
-            Expr::CallStruct { ref name, ref args } => {
-                let copy_self = self.clone();
-                match copy_self.get_ident(Ident(name.clone())) {
-                    Some(f) => match *f {
-                        Var{value: Value::DefStruct {
-                            ref fields,
-                            ..
-                        }, ..} => {
-                            let mut map = HashMap::new();
-                            let mut a ;
-                            let mut _v;
-                            for (arg, value) in args {
-                                a = match arg {
-                                    Expr::Ident { ref ident } => ident.clone(),
-                                    _ => {
-                                        return Err(Error::TypeMismatch(TypeMismatchError {
-                                            expected: Type::None,
-                                            found: Type::None,
-                                        }))
-                                    }
-                                };
-                                _v = self.eval_expr(value.clone())?;
-                                for field in fields {
-                                    let Ident(f) = field.clone();
-                                    if f == a {
-                                        map.insert(field.clone(), self.eval_expr(value.clone())?);
-                                    }
-
-                                }
-                            }
-                            Ok(Value::CallStruct {
-                                name: name.clone(),
-                                fields: map,
-                            })
-                        }
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Struct(name.clone()),
-                            found: f.value.get_type(),
-                        })),
-                    },
-                    None => Err(Error::StructNotFound(StructNotFoundError {
-                        name: name.clone(),
-                    })),
-                }
-            },
-            Expr::GetAttr { name , attr } => {
-                match self.get_ident(Ident(name.clone())) {
-                    Some(Var{value: Value::CallStruct { ref fields , ..}, ..}) => {
-                        match fields.get(&Ident(attr.clone())) {
-                            Some(v) => return Ok(v.clone()),
-                            None => {
-                                return Err(Error::AttrNotFound(AttrNotFoundError {
-                                    attr_name: attr
-                                }))
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Struct(name),
-                            found: Type::None,
-                        }))
+            Expr::With { name, value, body } => self.eval_with(name, *value, *body),
+            Expr::TryCatch { body, name, handler } => self.eval_try_catch(*body, name, *handler),
+        }
+    }
+
+    fn eval_custom_op(&mut self, op: String, left: Expr, right: Expr) -> Result<Value, Error> {
+        match self.operators.get(&op) {
+            Some(function) => self.eval_expr(Expr::Call {
+                name: function.clone(),
+                args: vec![left, right],
+            }),
+            None => Err(Error::OperatorNotFound(OperatorNotFoundError { op })),
+        }
+    }
+
+    fn eval_scoped_block(&mut self, body: Vec<Expr>) -> Result<Value, Error> {
+        if let Some(declared) = &self.declared {
+            declared.borrow_mut().push(body.iter().flat_map(|e| incremental::names(e).writes).collect());
+        }
+        self.push_scope();
+        let result = self.eval_block(body);
+        self.pop_scope();
+        if let Some(declared) = &self.declared {
+            declared.borrow_mut().pop();
+        }
+        result
+    }
+
+    fn eval_and(&mut self, left: Expr, right: Expr) -> Result<Value, Error> {
+        let left = self.eval_expr(left)?;
+        match left {
+            Value::Bool(false) => Ok(left),
+            Value::Bool(true) => {
+                let right = self.eval_expr(right)?;
+                left.and(&right)
+            }
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Bool,
+                found: left.get_type(),
+            })),
+        }
+    }
+
+    fn eval_or(&mut self, left: Expr, right: Expr) -> Result<Value, Error> {
+        let left = self.eval_expr(left)?;
+        match left {
+            Value::Bool(true) => Ok(left),
+            Value::Bool(false) => {
+                let right = self.eval_expr(right)?;
+                left.or(&right)
+            }
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Bool,
+                found: left.get_type(),
+            })),
+        }
+    }
+
+    fn eval_while(&mut self, cond: &Expr, body: &Expr) -> Result<Value, Error> {
+        while self.eval_expr(cond.clone())?.is_truthy() {
+            match self.eval_expr(body.clone()) {
+                Err(Error::Break) => break,
+                Err(Error::Continue) => continue,
+                result => result?,
+            };
+        }
+        Ok(Value::None)
+    }
+
+    fn eval_for_loop(&mut self, name: Expr, iter: Expr, body: &Expr) -> Result<Value, Error> {
+        let name_str = match name {
+            Expr::Ident { ident } => ident,
+            _ => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::String,
+                    found: Type::None,
+                }))
+            }
+        };
+
+        let iter = self.eval_expr(iter)?;
+        // The loop variable lives in a scope of its own, so it is gone after the loop.
+        self.push_scope();
+        let result = self.eval_for(&name_str, iter, body);
+        self.pop_scope();
+        result
+    }
+
+    fn eval_lambda(&mut self, args: &[Expr], body: Expr) -> Result<Value, Error> {
+        let args = param_names(args)?;
+        self.check_names("<lambda>", &args, &body);
+        Ok(Value::Function {
+            name: "<lambda>".to_string(),
+            func: function(body, self.scopes.clone()),
+            args,
+        })
+    }
+
+    fn eval_apply(&mut self, func: Expr, args: Vec<Expr>) -> Result<Value, Error> {
+        let func = self.eval_expr(func)?;
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.eval_expr(arg)?);
+        }
+        self.call_value(&func, values)
+    }
+
+    fn eval_tuple(&mut self, elems: &[Expr]) -> Result<Value, Error> {
+        self.check_length(elems.len())?;
+        let mut items = Vec::new();
+        for elem in elems {
+            items.push(self.eval_expr(elem.clone())?);
+        }
+        Ok(Value::Tuple(items))
+    }
+
+    fn eval_assign_tuple(&mut self, names: Vec<String>, value: Expr, mutable: bool) -> Result<Value, Error> {
+        let value_evaluate = self.eval_expr(value)?;
+        let items = match value_evaluate {
+            Value::Tuple(items) | Value::List(items) => items,
+            _ => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Tuple,
+                    found: value_evaluate.get_type(),
+                }))
+            }
+        };
+        if items.len() != names.len() {
+            return Err(Error::DestructureMismatch(DestructureMismatchError {
+                expected: names.len(),
+                found: items.len(),
+            }));
+        }
+        for name in names.iter() {
+            if !self.shadowing && self.get_ident(name).is_some() {
+                return Err(Error::VarAlreadyDefined(VarAlreadyDefinedError {
+                    var_name: name.clone(),
+                }));
+            }
+        }
+        for (name, item) in names.into_iter().zip(items) {
+            self.set_ident(Ident(name), Var {
+                type_: item.get_type(),
+                value: item,
+                mutable,
+            });
+        }
+        Ok(Value::None)
+    }
+
+    fn eval_fun_def(&mut self, name: &str, args: &[Expr], body: &Expr) -> Result<Value, Error> {
+        let args_vec = param_names(args)?;
+        self.check_names(name, &args_vec, body);
+        let value = Value::Function { name: name.to_string(), func: function(body.clone(), self.scopes.clone()), args: args_vec };
+        self.set_ident(
+            Ident(name.to_string()),
+            Var {
+                value: value.clone(),
+                type_: Type::Func,
+                mutable: false,
+            },
+        );
+        Ok(value)
+    }
+
+    fn eval_call(&mut self, name: &str, args: &[Expr]) -> Result<Value, Error> {
+        let callee = self.get_ident(name).map(|var| var.value);
+        match callee {
+            Some(f) => match f {
+                Value::Function {
+                    func,
+                    args: a,
+                    ..
+                } => {
+                    if args.len() < a.len() {
+                        return Err(Error::FunctionArgumentMismatch(FunctionArgumentMismatchError {
+                            name: name.to_string(),
+                            expected: a.len(),
+                            found: args.len(),
+                        }));
+                    }
+                    let mut values = Vec::new();
+                    for arg_value in args.iter() {
+                        values.push(self.eval_expr(arg_value.clone())?);
                     }
-                };
+
+                    let Function(f, _) = func;
+                    f(call_args(&a, values), self.clone())
+                },
+                _ => Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Func,
+                    found: f.get_type(),
+                })),
             },
-            Expr::Impl { ref name_struct , ref name_method, args, body } => {
-                let fiw;
-                let mut fuw;
-                match self.get_ident(Ident(name_struct.clone())) {
-                    Some(Var{value: Value::DefStruct { ref fields, ref function , ..}, ..}) => {
-                        fiw = fields.clone();
-                        fuw = function.clone();
-                    },
-                    None => {
-                        return Err(Error::StructNotFound(StructNotFoundError {
-                            name: name_struct.clone(),
-                        }))
+            None => Err(Error::FunctionNotFound(FunctionNotFoundError {
+                name: name.to_string(),
+            })),
+        }
+    }
+
+    fn eval_list(&mut self, elems: &[Expr]) -> Result<Value, Error> {
+        self.check_length(elems.len())?;
+        let mut list = Vec::new();
+        for elem in elems {
+            match elem {
+                Expr::Spread { value } => match self.eval_expr(*value.clone())? {
+                    Value::List(items) => {
+                        self.check_length(list.len() + items.len())?;
+                        list.extend(items);
                     }
-                    _ => {
+                    v => {
                         return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Struct(name_struct.clone()),
-                            found: Type::None,
+                            expected: Type::List,
+                            found: v.get_type(),
                         }))
                     }
-                };
-
-                let mut args_vec = Vec::new();
-                for arg in args {
-                    args_vec.push(match arg {
-                        Expr::Ident { ref ident } => ident.clone(),
-                        _ => {
-                            return Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::None,
-                                found: Type::None,
-                            }))
-                        }
-                    });
+                },
+                _ => list.push(self.eval_expr(elem.clone())?),
+            }
+        }
+        Ok(Value::List(list))
+    }
+
+    fn eval_map(&mut self, entries: Vec<(Expr, Expr)>) -> Result<Value, Error> {
+        self.check_length(entries.len())?;
+        let mut map = BTreeMap::new();
+        for (key, value) in entries {
+            let key = MapKey::from_value(&self.eval_expr(key)?)?;
+            map.insert(key, self.eval_expr(value)?);
+        }
+        Ok(Value::Map(map))
+    }
+
+    fn eval_index(&mut self, name: &Expr, index: &Expr) -> Result<Value, Error> {
+        let real_name = match *name {
+            Expr::Ident { ref ident } => ident.clone(),
+            ref base => base.kind().to_string(),
+        };
+        let base = self.eval_expr(name.clone())?;
+        if let Value::Map(map) = base {
+            let key = MapKey::from_value(&self.eval_expr(index.clone())?)?;
+            return match map.get(&key) {
+                Some(value) => Ok(value.clone()),
+                None => Err(Error::KeyNotFound(KeyNotFoundError {
+                    key: key.to_value().display_value(),
+                    name: real_name,
+                })),
+            };
+        }
+        // Strings are indexed by character, and slicing a string or a
+        // tuple gives back a value of the same type.
+        let base_type = base.get_type();
+        let list = match base {
+            Value::List(list) | Value::Tuple(list) => list,
+            Value::String(s) => s.chars().map(|c| Value::String(c.to_string())).collect::<Vec<Value>>(),
+            base => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::List,
+                    found: base.get_type(),
+                }))
+            }
+        };
+
+        let index = self.eval_expr(index.clone())?;
+        match index {
+            Value::Range(r) => {
+                if r.start < 0 || r.start > r.end || r.start >= list.len() as isize {
+                    return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
+                        index: r.start as i32,
+                        name: real_name,
+                    }));
                 }
-                let f = Value::Function { name: name_method.clone(), func: function(*body), args: args_vec };
-                fuw.insert(name_method.clone(), f);
-                self.set_ident(Ident(name_struct.clone()), Var {value: Value::DefStruct { name: name_struct.clone(), fields: fiw, function: fuw }, type_: Type::Struct(name_struct.clone()), mutable: false});
-                Ok(Value::None)
+
+                if r.end > list.len() as isize {
+                    return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
+                        index: r.end as i32,
+                        name: real_name,
+                    }));
+                }
+
+                let slice = list[r.start as usize..r.end as usize].to_vec();
+                match base_type {
+                    Type::String => Ok(Value::String(slice.iter().map(|c| c.display_value()).collect())),
+                    Type::Tuple => Ok(Value::Tuple(slice)),
+                    _ => Ok(Value::List(slice)),
+                }
+            }
+            index => {
+                let num = index.as_int()?;
+                if num < 0 || num as usize >= list.len() {
+                    return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
+                        index: num as i32,
+                        name: real_name,
+                    }));
+                }
+                Ok(list[num as usize].clone())
+            }
+        }
+    }
+
+    fn eval_struct_def(&mut self, name: &str, fields: &[Expr]) -> Result<Value, Error> {
+        let mut f = Vec::new();
+        
+        for field in fields {
+            match field {
+                Expr::Ident { ref ident } => f.push(ident.clone()),
+                _ => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::None,
+                        found: Type::None,
+                    }))
+                } 
+            }
+            
+        }
+        let mut nf = Vec::new();
+        for field in fields {
+            nf.push(match field {
+                Expr::Ident { ident } => Ident(ident.clone()),
+                _ => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::None,
+                        found: Type::None,
+                    }))
+                }
+            });
+        }
+        self.set_ident(Ident(name.to_string()), Var {
+            value:Value::DefStruct {
+                name: name.to_string(),
+                fields: nf,
+                function: HashMap::new()
             },
-            Expr::GetFunc { name , func , args } => {
-                let call_struct;
-                let s = match self.get_ident(Ident(name.clone())) {
-                    Some(Var {value: Value::CallStruct { name: n, fields: fi }, ..}) => {
-                        call_struct = Value::CallStruct { name: n.clone(), fields: fi.clone() };
-                        match &self.get_ident(Ident(n.clone())) {
-                            Some(Var{value: Value::DefStruct { fields: f, function: fu , ..}, ..}) => {
-                                match fu.get(&func) {
-                                    Some(v) => v.clone(),
-                                    None => {
-                                        return Err(Error::FunctionNotFound(FunctionNotFoundError {
-                                            name: func
-                                        }))
-                                    }
-                                }
+            type_: Type::Struct(name.to_string()),
+            mutable: false,
+        });
+        Ok(Value::None)
+    }
+
+    fn eval_call_struct(&mut self, name: &str, base: Option<&Expr>, args: &[(Expr, Expr)]) -> Result<Value, Error> {
+        match self.get_ident(name) {
+            Some(f) => match f {
+                Var{value: Value::DefStruct {
+                    ref fields,
+                    ..
+                }, ..} => {
+                    let mut map = match base {
+                        Some(base) => match self.eval_expr(base.clone())? {
+                            Value::CallStruct { name: ref base_name, fields } if base_name == name => fields,
+                            v => {
+                                return Err(Error::TypeMismatch(TypeMismatchError {
+                                    expected: Type::FieldStruct(name.to_string()),
+                                    found: v.get_type(),
+                                }))
                             }
+                        },
+                        None => HashMap::new(),
+                    };
+                    for (arg, value) in args {
+                        let field = match arg {
+                            Expr::Ident { ref ident } => Ident(ident.clone()),
                             _ => {
                                 return Err(Error::TypeMismatch(TypeMismatchError {
-                                    expected: Type::Struct(name.clone()),
+                                    expected: Type::None,
                                     found: Type::None,
                                 }))
                             }
+                        };
+                        if !fields.contains(&field) {
+                            return Err(Error::AttrNotFound(AttrNotFoundError {
+                                attr_name: field.0,
+                            }));
                         }
+                        let value = self.eval_expr(value.clone())?;
+                        map.insert(field, value);
                     }
-                    _ => {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Struct(name),
-                            found: Type::None,
-                        }))
-                    }
-                };
-                    
-                match s {
-                    Value::Function {func: f, args: a, ..} => {
-                        let Function(f) = f;
-                        let mut new_vm = Vm::new();
-                        let mut args_map = HashMap::new();
-                        for (argv, argn) in args.iter().zip(a) {
-                            let value = self.clone().eval_expr(argv.clone())?;
-                            args_map.insert(argn, Var {value: value.clone(), type_: value.get_type(), mutable: false});
-                        }
-                        new_vm.set_ident(Ident("self".to_string()), Var{value: call_struct, type_: Type::Struct(name), mutable: false});
+                    Ok(Value::CallStruct {
+                        name: name.to_string(),
+                        fields: map,
+                    })
+                }
+                _ => Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Struct(name.to_string()),
+                    found: f.value.get_type(),
+                })),
+            },
+            None => Err(Error::StructNotFound(StructNotFoundError {
+                name: name.to_string(),
+            })),
+        }
+    }
 
-                        
-                        return f(args_map, new_vm);
-                    },
+    fn eval_get_attr(&mut self, name: String, attr: String, optional: bool) -> Result<Value, Error> {
+        match self.get_ident(&name) {
+            Some(Var{value: Value::None, ..}) if optional => Ok(Value::None),
+            Some(Var{value: Value::CallStruct { mut fields , ..}, ..}) => {
+                fields.remove(&Ident(attr.clone())).ok_or(Error::AttrNotFound(AttrNotFoundError {
+                    attr_name: attr
+                }))
+            }
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Struct(name),
+                found: var.value.get_type(),
+            })),
+            None => Err(Error::VarNotFound(VarNotFoundError {
+                var_name: name,
+            })),
+        }
+    }
+
+    fn eval_impl(&mut self, name_struct: &str, name_method: &str, args: Vec<Expr>, body: Expr) -> Result<Value, Error> {
+        let fiw;
+        let mut fuw;
+        match self.get_ident(name_struct) {
+            Some(Var{value: Value::DefStruct { ref fields, ref function , ..}, ..}) => {
+                fiw = fields.clone();
+                fuw = function.clone();
+            },
+            None => {
+                return Err(Error::StructNotFound(StructNotFoundError {
+                    name: name_struct.to_string(),
+                }))
+            }
+            _ => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Struct(name_struct.to_string()),
+                    found: Type::None,
+                }))
+            }
+        };
+
+        let mut args_vec = Vec::new();
+        for arg in args {
+            args_vec.push(match arg {
+                Expr::Ident { ref ident } => ident.clone(),
+                _ => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::None,
+                        found: Type::None,
+                    }))
+                }
+            });
+        }
+        let f = Value::Function { name: name_method.to_string(), func: function(body, self.scopes.clone()), args: args_vec };
+        fuw.insert(name_method.to_string(), f);
+        self.update_ident(Ident(name_struct.to_string()), Var {value: Value::DefStruct { name: name_struct.to_string(), fields: fiw, function: fuw }, type_: Type::Struct(name_struct.to_string()), mutable: false});
+        Ok(Value::None)
+    }
+
+    fn eval_method_call(&mut self, name: String, func: String, args: Vec<Expr>, optional: bool) -> Result<Value, Error> {
+        let call_struct;
+        let s = match self.get_ident(&name) {
+            Some(Var {value: Value::None, ..}) if optional => return Ok(Value::None),
+            Some(Var {value: Value::CallStruct { name: n, fields: fi }, ..}) => {
+                call_struct = Value::CallStruct { name: n.clone(), fields: fi.clone() };
+                match &self.get_ident(&n) {
+                    Some(Var{value: Value::DefStruct { function: fu, .. }, ..}) => {
+                        match fu.get(&func) {
+                            Some(v) => v.clone(),
+                            None => {
+                                return Err(Error::FunctionNotFound(FunctionNotFoundError {
+                                    name: func
+                                }))
+                            }
+                        }
+                    }
                     _ => {
                         return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Func,
+                            expected: Type::Struct(name.clone()),
                             found: Type::None,
                         }))
                     }
                 }
-                                
-            },
-            Expr::SetVar { name, value } => {
-                let v = self.eval_expr(*value.clone())?;
-                if let None = self.get_ident(Ident(name.clone())) {
-                    return Err(Error::VarNotFound(VarNotFoundError {
-                        var_name: name.clone(),
+            }
+            _ => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Struct(name),
+                    found: Type::None,
+                }))
+            }
+        };
+            
+        match s {
+            Value::Function {func: f, args: a, ..} => {
+                if args.len() != a.len() {
+                    return Err(Error::FunctionArgumentMismatch(FunctionArgumentMismatchError {
+                        name: func,
+                        expected: a.len(),
+                        found: args.len(),
                     }));
-                } else if let Some(var) = self.get_ident(Ident(name.clone())) {
-                    if ! var.mutable {
-                        return Err(Error::ItsAConstant(ItsAConstantError {
-                            var_name: name
-                        }))
-                    }
-                    if var.type_ != v.get_type() {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: var.type_.clone(),
-                            found: v.get_type()
-                        }))
-                    }
                 }
+                let Function(f, _) = f;
+                let mut args_map = HashMap::new();
+                for (argv, argn) in args.iter().zip(a) {
+                    let value = self.eval_expr(argv.clone())?;
+                    args_map.insert(argn, Var {value: value.clone(), type_: value.get_type(), mutable: false});
+                }
+                args_map.insert("self".to_string(), Var{value: call_struct, type_: Type::Struct(name), mutable: false});
 
-                
-                self.set_ident(Ident(name), Var {value: v.clone(), type_: v.get_type(), mutable: true});
-                Ok(Value::None)
+                return f(args_map, self.clone());
             },
-            Expr::IOp { op, name, value } => {
-                let v = self.eval_expr(*value.clone())?;
-                match op {
-                    IOp::IAdd => self.iadd(name, v),
-                    IOp::ISub => self.isub(name, v),
-                    IOp::IMul => self.imul(name, v),
-                    IOp::IDiv => self.idiv(name, v)
+            _ => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Func,
+                    found: Type::None,
+                }))
+            }
+        }
+                        
+    }
+
+    fn eval_set_attr(&mut self, name: String, attr: String, value: Expr) -> Result<Value, Error> {
+        let var = match self.get_ident(&name) {
+            Some(var) if !var.mutable => {
+                return Err(Error::CannotMutate(CannotMutateError { var_name: name }))
+            }
+            Some(var) => var,
+            None => return Err(Error::VarNotFound(VarNotFoundError { var_name: name })),
+        };
+        let (struct_name, mut fields) = match var.value {
+            Value::CallStruct { ref name, ref fields } => (name.clone(), fields.clone()),
+            ref other => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Struct(name),
+                    found: other.get_type(),
+                }))
+            }
+        };
+        if !fields.contains_key(attr.as_str()) {
+            return Err(Error::AttrNotFound(AttrNotFoundError { attr_name: attr }));
+        }
+        let value = self.eval_expr(value)?;
+        fields.insert(Ident(attr), value);
+        self.update_ident(Ident(name), Var { value: Value::CallStruct { name: struct_name, fields }, ..var });
+        Ok(Value::None)
+    }
+
+    fn eval_set_index(&mut self, name: String, index: Expr, value: Expr) -> Result<Value, Error> {
+        let var = match self.get_ident(&name) {
+            Some(var) if !var.mutable => {
+                return Err(Error::CannotMutate(CannotMutateError { var_name: name }))
+            }
+            Some(var) => var,
+            None => return Err(Error::VarNotFound(VarNotFoundError { var_name: name })),
+        };
+        let mut list = match var.value {
+            Value::List(ref list) => list.clone(),
+            ref other => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::List,
+                    found: other.get_type(),
+                }))
+            }
+        };
+        let index = self.eval_expr(index)?.as_int()?;
+        let value = self.eval_expr(value)?;
+        if index < 0 || (index as usize >= list.len() && !self.grow_lists) {
+            return Err(Error::IndexOutOfBounds(IndexOutOfBoundsError {
+                index: index as i32,
+                name,
+            }));
+        }
+        let i = index as usize;
+        if i >= list.len() {
+            self.check_length(i + 1)?;
+            list.resize(i + 1, Value::None);
+        }
+        list[i] = value;
+        self.update_ident(Ident(name), Var { value: Value::List(list), ..var });
+        Ok(Value::None)
+    }
+
+    fn eval_swap(&mut self, a: String, b: String) -> Result<Value, Error> {
+        let mut vars = Vec::new();
+        for name in [&a, &b] {
+            match self.get_ident(name) {
+                Some(var) if !var.mutable => {
+                    return Err(Error::CannotMutate(CannotMutateError {
+                        var_name: name.clone()
+                    }))
                 }
-            },
-            Expr::Match { value, cases } => {
-                let mut return_value = Value::None;
-                for i in cases {
-                    let _case = self.eval_expr(i.0);
-                    match self.eval_expr(*value.clone())?.clone() {
-                        _case => {
-                            let mut new_vm = Vm::new();
-                            return_value = new_vm.eval_expr(i.1)?;
-                        }
-                    }
+                Some(var) => vars.push(var),
+                None => {
+                    return Err(Error::VarNotFound(VarNotFoundError {
+                        var_name: name.clone(),
+                    }))
+                }
+            }
+        }
+        let (var_b, var_a) = (vars.pop().unwrap(), vars.pop().unwrap());
+        if var_a.type_ != var_b.type_ {
+            return Err(Error::TypeMismatch(TypeMismatchError {
+                expected: var_a.type_,
+                found: var_b.type_
+            }))
+        }
+        self.update_ident(Ident(a), Var {value: var_b.value, ..var_a.clone()});
+        self.update_ident(Ident(b), Var {value: var_a.value, ..var_b});
+        Ok(Value::None)
+    }
+
+    fn eval_match(&mut self, value: Expr, cases: Vec<(Expr, Expr)>) -> Result<Value, Error> {
+        let value = self.eval_expr(value)?;
+        for (pattern, body) in cases {
+            let is_match = match pattern {
+                Expr::Ident { ref ident } if ident == "_" => true,
+                pattern => matches!(value.eq(&self.eval_expr(pattern)?), Ok(Value::Bool(true))),
+            };
+            if is_match {
+                return self.eval_expr(body);
+            }
+        }
+        Ok(Value::None)
+    }
+
+    fn eval_enum_call(&mut self, name: String, field: String) -> Result<Value, Error> {
+        match self.get_ident(&name) {
+            Some(Var{value: Value::Enum { variants: fields }, ..}) => {
+                if fields.contains(&field) {
+                    Ok(Value::EnumCall { name: name.clone(), field: field.clone() })
+                } else {
+                    Err(Error::FieldEnumNotFound(FieldEnumNotFoundError {
+                        name: name.clone(),
+                        field: field.clone(),
+                    }))
                 }
-                Ok(return_value)
             },
-            Expr::Enum { name, fields } => {
+            Some(e) => {
+                Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Enum,
+                    found: e.value.get_type(),
+                }))
+            }
+            _ => {
+                Err(Error::EnumNotFound(EnumNotFoundError {
+                    name: name.clone()
+                }))
+            }
+        }
+    }
+
+    fn eval_with(&mut self, name: String, value: Expr, body: Expr) -> Result<Value, Error> {
+        let resource = self.eval_expr(value)?;
+        let closes = match &resource {
+            Value::CallStruct { name: s, .. } => matches!(
+                self.get_ident(s),
+                Some(Var { value: Value::DefStruct { function, .. }, .. }) if function.contains_key("close")
+            ),
+            _ => false,
+        };
+        self.push_scope();
+        self.set_ident(Ident(name.clone()), Var {
+            type_: resource.get_type(),
+            value: resource,
+            mutable: false,
+        });
+        let result = self.eval_expr(body);
+        let closed = if closes {
+            self.eval_expr(Expr::GetFunc { name, func: "close".to_string(), args: vec![], optional: false })
+        } else {
+            Ok(Value::None)
+        };
+        self.pop_scope();
+        // An error from the body takes precedence over one from `close`.
+        match (result, closed) {
+            (Err(err), _) | (Ok(_), Err(err)) => Err(err),
+            (Ok(value), Ok(_)) => Ok(value),
+        }
+    }
+
+    fn eval_try_catch(&mut self, body: Expr, name: String, handler: Expr) -> Result<Value, Error> {
+        match self.eval_expr(body) {
+            Ok(v) => Ok(v),
+            Err(err) if err.is_control_flow() => Err(err),
+            Err(err) => {
+                self.push_scope();
                 self.set_ident(Ident(name), Var {
-                    value: Value::Enum { variants: fields },
-                    type_: Type::Enum,
-                    mutable: false
+                    value: err.to_value(),
+                    type_: Type::FieldStruct("Error".to_string()),
+                    mutable: false,
                 });
-                Ok(Value::None)
+                let result = self.eval_expr(handler);
+                self.pop_scope();
+                result
             }
-            Expr::EnumCall { ref name, field } => {
-                match self.get_ident(Ident(name.to_string())) {
-                    Some(Var{value: Value::Enum { variants: fields }, ..}) => {
-                        if fields.contains(&field) {
-                            Ok(Value::EnumCall { name: name.clone(), field: field.clone() })
-                        } else {
-                            Err(Error::FieldEnumNotFound(FieldEnumNotFoundError {
-                                name: name.clone(),
-                                field: field.clone(),
-                            }))
-                        }
-                    },
-                    Some(e) => {
-                        Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Enum,
-                            found: e.value.get_type(),
-                        }))
-                    }
-                    _ => {
-                        Err(Error::EnumNotFound(EnumNotFoundError {
-                            name: name.clone()
-                        }))
-                    }
-                }
-            },
-            Expr::To { value, to } => {
-                let v = self.eval_expr(*value.clone())?;
-                match to {
-                    Type::Int => {
-                        match v {
-                            Value::Number(i) => Ok(Value::Number(i)),
-                            Value::String(s) => {
-                                Ok(Value::Number(match s.parse::<i32>() {
-                                    Ok(i) => i,
-                                    Err(_) => {
-                                        return Err(Error::InvalidCastNumber(InvalidCastNumberError {
-                                            elt: s.clone()
-                                        }))
-                                    }
-                                } as f64))
-                            },
-                            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::Int,
-                                found: v.get_type(),
-                            })),
-                        }
-                    },
-                    Type::String => {
-                        match v {
-                            Value::String(s) => Ok(Value::String(s)),
-                            Value::Number(i) => Ok(Value::String(i.to_string())),
-                            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::None,
-                                found: v.get_type(),
-                            })),
-                        }
-                    },
-                    Type::Bool => {
-                        match v {
-                            Value::Bool(b) => Ok(Value::Bool(b)),
-                            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                                expected: Type::Bool,
-                                found: v.get_type(),
-                            })),
-                        }
-                    },
-                    _ => Err(Error::TypeMismatch(TypeMismatchError {
-                        expected: Type::None,
-                        found: v.get_type(),
-                    })), 
-                }
-            }        
         }
     }
     
 
-    pub fn set_ident(&mut self, ident: Ident, value: Var) {
-        self.0.insert(ident.clone(), value);
+    /// Evaluates each top-level statement of `src` in the current scope, so
+    /// bindings persist across calls, and returns one value per statement.
+    pub fn eval_many(&mut self, src: &str) -> Result<Vec<Value>, Error> {
+        let program = crate::tlang::ExprsParser::new()
+            .parse(src)
+            .map_err(|e| Error::Parse(ParseError {
+                message: e.to_string(),
+            }))?;
+        let body = match program {
+            Expr::Block { body } => body,
+            expr => vec![expr],
+        };
+        body.into_iter().map(|expr| self.eval_expr(expr)).collect()
     }
 
-    pub fn get_ident(&self, ident: Ident) -> Option<&Var> {
-        
-        self.0.get(&ident)
+    fn eval_for(&mut self, name: &str, iter: Value, body: &Expr) -> Result<Value, Error> {
+        let mut last = Value::None;
+        for item in for_items(iter)? {
+            self.bind_loop_var(name, item);
+            last = match self.eval_expr(body.clone()) {
+                Err(Error::Break) => break,
+                Err(Error::Continue) => continue,
+                result => result?,
+            };
+        }
+        Ok(last)
+    }
 
+    fn bind_loop_var(&mut self, name: &str, item: Value) {
+        self.set_ident(Ident(name.to_string()), Var {
+            type_: item.get_type(),
+            value: item,
+            mutable: true,
+        });
     }
 
-    pub fn iadd(&mut self, a: String, b: Value) -> Result<Value, Error> {
-        match b {
-            Value::Number(b) => {
-                if self.exists(Ident(a.clone())) {
-                    let v = self.get_ident(Ident(a.clone())).unwrap().clone();
-                    if ! v.mutable {
-                        return Err(Error::ItsAConstant(ItsAConstantError {
-                            var_name: a
-                        }))
-                    }
+    fn eval_block(&mut self, body: Vec<Expr>) -> Result<Value, Error> {
+        let mut last = Value::None;
+        for expr in body {
+            last = self.eval_expr(expr)?;
+        }
+        Ok(last)
+    }
 
-                    let r = match v.value {
-                        Value::Number(n) => {
-                            self.set_ident(Ident(a), Var{value: Value::Number(n + b), type_: v.clone().type_, mutable: v.clone().mutable});
-                            Ok(Value::None)
-                        },
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: v.value.get_type(),
-                        })),
-                    }?; 
-                    if r.get_type() != v.clone().type_ {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: v.type_,
-                            found: r.get_type()
-                        }))
-                    } else {
-                        Ok(r)
+    pub fn push_scope(&mut self) {
+        let scope = self
+            .pool
+            .as_ref()
+            .and_then(|pool| pool.borrow_mut().pop())
+            .unwrap_or_default();
+        self.scopes.push(Rc::new(RefCell::new(scope)));
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            if let Some(scope) = self.scopes.pop() {
+                if let (Some(pool), Ok(scope)) = (&self.pool, Rc::try_unwrap(scope)) {
+                    let mut pool = pool.borrow_mut();
+                    if pool.len() < MAX_POOLED_SCOPES {
+                        let mut scope = scope.into_inner();
+                        scope.clear();
+                        pool.push(scope);
                     }
-                } else {
-                    return Err(Error::VarNotFound(VarNotFoundError {
-                        var_name: a,
-                    }));
                 }
-            },
-            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                expected: Type::Int,
-                found: Type::None,
-            })),
+            }
         }
     }
 
-    pub fn isub(&mut self, a: String, b: Value) -> Result<Value, Error> {
-        match b {
-            Value::Number(b) => {
-                if self.exists(Ident(a.clone())) {
-                    let v = self.get_ident(Ident(a.clone())).unwrap().clone();
-                    if ! v.mutable {
-                        return Err(Error::ItsAConstant(ItsAConstantError {
-                            var_name: a
-                        }))
-                    }
-                    let r = match v.value {
-                        Value::Number(n) => {
-                            self.set_ident(Ident(a), Var{value: Value::Number(n - b), type_: v.clone().type_, mutable: v.clone().mutable});
-                            Ok(Value::None)
-                        },
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: v.value.get_type(),
-                        })),
-                    }?; 
-                    if r.get_type() != v.clone().type_ {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: v.type_,
-                            found: r.get_type()
-                        }))
-                    } else {
-                        Ok(r)
-                    }
-                } else {
-                    return Err(Error::VarNotFound(VarNotFoundError {
-                        var_name: a,
-                    }));
-                }
-            },
-            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                expected: Type::Int,
-                found: Type::None,
-            })),
+    pub fn set_ident(&mut self, ident: Ident, value: Var) {
+        self.scopes.last().unwrap().borrow_mut().insert(ident, value);
+    }
+
+    /// Replaces `ident` in the scope it was defined in, or defines it in the
+    /// innermost scope when it does not exist yet.
+    pub fn update_ident(&mut self, ident: Ident, value: Var) {
+        match self.scopes.iter().rev().find(|scope| scope.borrow().contains_key(&ident)) {
+            Some(scope) => {
+                scope.borrow_mut().insert(ident, value);
+            }
+            None => self.set_ident(ident, value),
         }
     }
 
-    pub fn imul(&mut self, a: String, b: Value) -> Result<Value, Error> {
-        match b {
-            Value::Number(b) => {
-                if self.exists(Ident(a.clone())) {
-                    let v = self.get_ident(Ident(a.clone())).unwrap().clone();
-                    if ! v.mutable {
-                        return Err(Error::ItsAConstant(ItsAConstantError {
-                            var_name: a
-                        }))
-                    }
-                    let r = match v.value {
-                        Value::Number(n) => {
-                            self.set_ident(Ident(a), Var{value: Value::Number(n * b), type_: v.clone().type_, mutable: v.clone().mutable});
-                            Ok(Value::None)
-                        },
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: v.value.get_type(),
-                        })),
-                    }?; 
-                    if r.get_type() != v.clone().type_ {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: v.type_,
-                            found: r.get_type()
-                        }))
-                    } else {
-                        Ok(r)
-                    }
-                } else {
-                    return Err(Error::VarNotFound(VarNotFoundError {
-                        var_name: a,
-                    }));
+    pub fn get_ident(&self, name: &str) -> Option<Var> {
+        self.scopes.iter().rev().find_map(|scope| scope.borrow().get(name).cloned())
+    }
+
+    fn set_var(&mut self, name: String, v: Value) -> Result<Value, Error> {
+        if let None = self.get_ident(&name) {
+            return Err(Error::VarNotFound(VarNotFoundError {
+                var_name: name.clone(),
+            }));
+        } else if let Some(var) = self.get_ident(&name) {
+            if ! var.mutable {
+                return Err(Error::CannotMutate(CannotMutateError {
+                    var_name: name
+                }))
+            }
+            if var.type_ != v.get_type() {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: var.type_.clone(),
+                    found: v.get_type()
+                }))
+            }
+        }
+
+        
+        self.update_ident(Ident(name), Var {value: v.clone(), type_: v.get_type(), mutable: true});
+        Ok(Value::None)
+    }
+
+    fn binary(&self, op: &Op, left: Value, right: Value) -> Result<Value, Error> {
+        Ok(match op {
+            Op::Add => left.add(&right)?,
+            Op::Sub => left.sub(&right)?,
+            Op::Mul => {
+                if let Some(length) = left.repeat_length(&right) {
+                    self.check_length(length)?;
                 }
+                left.mul(&right)?
             },
-            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                expected: Type::Int,
-                found: Type::None,
-            })),
-        }
+            Op::Div => left.div(&right)?,
+            Op::Mod => left.modulo(&right)?,
+            Op::Pow => left.pow(&right)?,
+            Op::Eq => left.eq(&right)?,
+            Op::Neq => left.neq(&right)?,
+            Op::Gt => left.gt(&right)?,
+            Op::Lt => left.lt(&right)?,
+            Op::Ge => left.ge(&right)?,
+            Op::Le => left.le(&right)?,
+            Op::And => left.and(&right)?,
+            Op::Or => left.or(&right)?,
+        })
     }
 
-    pub fn idiv(&mut self, a: String, b: Value) -> Result<Value, Error> {
-        match b {
-            Value::Number(b) => {
-                if self.exists(Ident(a.clone())) {
-                    let v = self.get_ident(Ident(a.clone())).unwrap().clone();
-                    if ! v.mutable {
-                        return Err(Error::ItsAConstant(ItsAConstantError {
-                            var_name: a
-                        }))
-                    }
-                    let r = match v.value {
-                        Value::Number(n) => {
-                            self.set_ident(Ident(a), Var{value: Value::Number(n / b), type_: v.clone().type_, mutable: v.clone().mutable});
-                            Ok(Value::None)
-                        },
-                        _ => Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: Type::Int,
-                            found: v.value.get_type(),
-                        })),
-                    }?; 
-                    if r.get_type() != v.clone().type_ {
-                        return Err(Error::TypeMismatch(TypeMismatchError {
-                            expected: v.type_,
-                            found: r.get_type()
-                        }))
-                    } else {
-                        Ok(r)
-                    }
-                } else {
-                    return Err(Error::VarNotFound(VarNotFoundError {
-                        var_name: a,
+    // `let` binds in the innermost scope, replacing or shadowing any
+    // previous binding; `:=` is the path that mutates one in place.
+    fn define(&mut self, name: String, value_evaluate: Value, mutable: bool, type_: Option<Type>) -> Result<Value, Error> {
+        if !self.shadowing && self.get_ident(&name).is_some() {
+            return Err(Error::VarAlreadyDefined(VarAlreadyDefinedError {
+                var_name: name,
+            }));
+        }
+        match type_ {
+            Some(type_) => {
+                if value_evaluate.get_type() != type_ {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: type_,
+                        found: value_evaluate.get_type(),
                     }));
                 }
             },
-            _ => Err(Error::TypeMismatch(TypeMismatchError {
-                expected: Type::Int,
-                found: Type::None,
-            })),
+            None => {},
+        }
+
+        self.set_ident(Ident(name), Var {
+            value: value_evaluate.clone(),
+            type_: match value_evaluate {
+                Value::Int(_) => Type::Int,
+                Value::Float(_) => Type::Float,
+                Value::String(_) => Type::String,
+                Value::Bool(_) => Type::Bool,
+                Value::Function { .. } => Type::Func,
+                Value::DefStruct { name, fields, function } => Type::Struct(name),
+                Value::CallStruct { name, fields } => Type::FieldStruct(name),
+                Value::List(_) => Type::List,
+                Value::Tuple(_) => Type::Tuple,
+                Value::Map(_) => Type::Map,
+                Value::Range(_) => Type::Range,
+                Value::Enum { variants } => Type::Enum,
+                Value::EnumCall { name, field } => Type::FieldEnum(name),
+                Value::None => Type::None,
+            },
+            mutable,
+        });
+        Ok(Value::None)
+    }
+
+    fn compound_assign(&mut self, a: String, b: Value, op: Op) -> Result<Value, Error> {
+        let v = match self.get_ident(&a) {
+            Some(v) => v,
+            None => {
+                return Err(Error::VarNotFound(VarNotFoundError {
+                    var_name: a,
+                }))
+            }
+        };
+        if !v.mutable {
+            return Err(Error::CannotMutate(CannotMutateError {
+                var_name: a
+            }))
+        }
+        let r = self.binary(&op, v.value.clone(), b)?;
+        if r.get_type() != v.type_ {
+            return Err(Error::TypeMismatch(TypeMismatchError {
+                expected: v.type_,
+                found: r.get_type()
+            }))
         }
+        self.update_ident(Ident(a), Var{value: r, type_: v.type_, mutable: v.mutable});
+        Ok(Value::None)
     }
 
+    pub fn iadd(&mut self, a: String, b: Value) -> Result<Value, Error> {
+        self.compound_assign(a, b, Op::Add)
+    }
+
+    pub fn isub(&mut self, a: String, b: Value) -> Result<Value, Error> {
+        self.compound_assign(a, b, Op::Sub)
+    }
 
+    pub fn imul(&mut self, a: String, b: Value) -> Result<Value, Error> {
+        self.compound_assign(a, b, Op::Mul)
+    }
+
+    pub fn idiv(&mut self, a: String, b: Value) -> Result<Value, Error> {
+        self.compound_assign(a, b, Op::Div)
+    }
 
-    pub fn exists(&self, ident: Ident) -> bool {
-        self.0.contains_key(&ident)
+    pub fn exists(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.borrow().contains_key(name))
     }
 
 
@@ -1,6 +1,6 @@
 
 
-use std::{ops::Range, fmt, hash::Hash, collections::HashMap, rc::Rc};
+use std::{cmp::Ordering, ops::Range, fmt, hash::Hash, collections::{BTreeMap, HashMap}, rc::Rc, borrow::Borrow};
 
 use super::*;
 
@@ -17,12 +17,21 @@ macro_rules! build_enum {
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Ident(pub String);
 
+impl Borrow<str> for Ident {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub enum Type {
     Int, 
+    Float,
     String,
     Bool,
     List,
+    Tuple,
+    Map,
     Func,
     Range,
     Enum,
@@ -32,11 +41,126 @@ pub enum Type {
     None
 }
 
-pub struct Function(pub Rc<dyn Fn(HashMap<String, Var>, Vm) -> Result<Value, Error>>);
+impl Type {
+    pub fn name(&self) -> String {
+        match self {
+            Type::Int => "int".to_string(),
+            Type::Float => "float".to_string(),
+            Type::String => "string".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::List => "list".to_string(),
+            Type::Tuple => "tuple".to_string(),
+            Type::Map => "map".to_string(),
+            Type::Func => "function".to_string(),
+            Type::Range => "range".to_string(),
+            Type::Enum => "enum".to_string(),
+            Type::Struct(_) => "struct".to_string(),
+            Type::FieldEnum(name) | Type::FieldStruct(name) => name.clone(),
+            Type::None => "none".to_string(),
+        }
+    }
+
+    /// The variant as written in Rust, with its struct or enum name, such as
+    /// `FieldStruct(Point)` for a constructed `Point`.
+    pub fn variant_name(&self) -> String {
+        match self {
+            Type::Struct(name) => format!("Struct({})", name),
+            Type::FieldStruct(name) => format!("FieldStruct({})", name),
+            Type::FieldEnum(name) => format!("FieldEnum({})", name),
+            other => format!("{:?}", other),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Type {
+        match name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "string" => Type::String,
+            "bool" => Type::Bool,
+            "list" => Type::List,
+            "tuple" => Type::Tuple,
+            "map" => Type::Map,
+            "function" => Type::Func,
+            "range" => Type::Range,
+            "enum" => Type::Enum,
+            "none" => Type::None,
+            _ => Type::FieldStruct(name.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeInfo {
+    Simple(Type),
+    List(Box<TypeInfo>),
+    Tuple(Vec<TypeInfo>),
+    Struct {
+        name: String,
+        fields: Vec<(String, TypeInfo)>,
+    },
+    Mixed,
+    Unknown,
+}
+
+impl TypeInfo {
+    pub fn name(&self) -> String {
+        match self {
+            TypeInfo::Simple(t) => t.name(),
+            TypeInfo::List(t) => format!("list<{}>", t.name()),
+            TypeInfo::Tuple(items) => format!(
+                "tuple<{}>",
+                items.iter().map(|t| t.name()).collect::<Vec<String>>().join(", ")
+            ),
+            TypeInfo::Struct { name, fields } => format!(
+                "{} {{ {} }}",
+                name,
+                fields.iter().map(|(k, t)| format!("{}: {}", k, t.name())).collect::<Vec<String>>().join(", ")
+            ),
+            TypeInfo::Mixed => "mixed".to_string(),
+            TypeInfo::Unknown => "unknown".to_string(),
+        }
+    }
+}
+
+/// The hashable values that may be used as map keys. Keys are kept sorted so
+/// maps display deterministically.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Result<MapKey, Error> {
+        match value {
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Int(n) => Ok(MapKey::Int(*n)),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::String,
+                found: value.get_type(),
+            })),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Int(n) => Value::Int(*n),
+            MapKey::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+/// A callable, with the body it was defined with when it comes from a `def`
+/// so it can be rebuilt on another thread. Builtins and host functions have none.
+pub struct Function(pub Rc<dyn Fn(HashMap<String, Var>, Vm) -> Result<Value, Error>>, pub Option<Rc<Expr>>);
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    Number(f64),
+    Int(i64),
+    Float(f64),
     String(String),
     Bool(bool),
     Function {
@@ -54,6 +178,8 @@ pub enum Value {
         fields: HashMap<Ident, Value>,
     },
     List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Map(BTreeMap<MapKey, Value>),
     Range(Range<isize>),
     Enum {
         variants: Vec<String>,
@@ -65,6 +191,16 @@ pub enum Value {
     None,
 }
 
+/// How `print` and `format` render values. The default leaves numbers
+/// unformatted and writes `None` out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DisplayFormat {
+    pub thousands_separator: bool,
+    pub precision: Option<usize>,
+    /// Renders a `None` argument as nothing; one inside a list still shows.
+    pub empty_none: bool,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Var {
     pub value: Value,
@@ -75,7 +211,7 @@ pub struct Var {
 
 impl Clone for Function {
     fn clone(&self) -> Self {
-        Function(self.0.clone())
+        Function(self.0.clone(), self.1.clone())
     }
 }
 
@@ -89,12 +225,12 @@ impl fmt::Debug for Function {
 
 impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
-        &self.0 as *const _ == &other.0 as *const _
+        Rc::ptr_eq(&self.0, &other.0)
     }
 }
 impl Hash for Function {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        (&self.0 as *const _ as usize).hash(state);
+        (Rc::as_ptr(&self.0) as *const () as usize).hash(state);
     }
 }
 
@@ -103,60 +239,180 @@ impl Eq for Function {
 }
 
 impl Value {
+    fn promote(&self, other: &Value) -> Option<(f64, f64)> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some((*a as f64, *b as f64)),
+            (Value::Int(a), Value::Float(b)) => Some((*a as f64, *b)),
+            (Value::Float(a), Value::Int(b)) => Some((*a, *b as f64)),
+            (Value::Float(a), Value::Float(b)) => Some((*a, *b)),
+            _ => None,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Value::Int(n) => *n == 0,
+            Value::Float(n) => *n == 0.0,
+            _ => false,
+        }
+    }
+
     pub fn add(&self, other: &Value) -> Result<Value, Error> {
+        let error = || Error::CannotAdd(CannotAddError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
-            _ => Err(Error::CannotAdd(CannotAddError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
+            (Value::Int(a), Value::Int(b)) => a.checked_add(*b).map(Value::Int).ok_or_else(error),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a + b)),
+                None => Err(error()),
+            },
         }
     }
+
     pub fn sub(&self, other: &Value) -> Result<Value, Error> {
+        let error = || Error::CannotSub(CannotSubError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
-            _ => Err(Error::CannotSub(CannotSubError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
+            (Value::Int(a), Value::Int(b)) => a.checked_sub(*b).map(Value::Int).ok_or_else(error),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a - b)),
+                None => Err(error()),
+            },
         }
     }
 
     pub fn mul(&self, other: &Value) -> Result<Value, Error> {
+        let error = || Error::CannotMul(CannotMulError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
-            _ => Err(Error::CannotMul(CannotMulError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
+            (Value::Int(a), Value::Int(b)) => a.checked_mul(*b).map(Value::Int).ok_or_else(error),
+            (Value::String(s), n @ (Value::Int(_) | Value::Float(_)))
+            | (n @ (Value::Int(_) | Value::Float(_)), Value::String(s)) => Ok(Value::String(s.repeat(n.as_index("*", "count")?))),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a * b)),
+                None => Err(error()),
+            },
         }
     }
 
     pub fn div(&self, other: &Value) -> Result<Value, Error> {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
-            _ => Err(Error::CannotDiv(CannotDivError {
+        let error = || Error::CannotDiv(CannotDivError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
+        if self.promote(other).is_some() && other.is_zero() {
+            return Err(Error::DivisionByZero(DivisionByZeroError {
                 left: self.to_string(),
-                right: other.to_string(),
-            })),
+            }));
+        }
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.checked_div(*b).map(Value::Int).ok_or_else(error),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a / b)),
+                None => Err(error()),
+            },
         }
     }
 
     pub fn modulo(&self, other: &Value) -> Result<Value, Error> {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a % b)),
-            _ => Err(Error::CannotMod(CannotModError {
+        let error = || Error::CannotMod(CannotModError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
+        if self.promote(other).is_some() && other.is_zero() {
+            return Err(Error::DivisionByZero(DivisionByZeroError {
                 left: self.to_string(),
-                right: other.to_string(),
+            }));
+        }
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.checked_rem(*b).map(Value::Int).ok_or_else(error),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a % b)),
+                None => Err(error()),
+            },
+        }
+    }
+
+    pub fn pow(&self, other: &Value) -> Result<Value, Error> {
+        let error = || Error::CannotPow(CannotPowError {
+            left: self.to_string(),
+            right: other.to_string(),
+        });
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) if *b >= 0 => u32::try_from(*b)
+                .ok()
+                .and_then(|b| a.checked_pow(b))
+                .map(Value::Int)
+                .ok_or_else(error),
+            _ => match self.promote(other) {
+                Some((a, b)) => Ok(Value::Float(a.powf(b))),
+                None => Err(error()),
+            },
+        }
+    }
+
+    pub fn neg(&self) -> Result<Value, Error> {
+        let error = || Error::TypeMismatch(TypeMismatchError {
+            expected: Type::Int,
+            found: self.get_type(),
+        });
+        match self {
+            Value::Int(n) => n.checked_neg().map(Value::Int).ok_or_else(error),
+            Value::Float(n) => Ok(Value::Float(-n)),
+            _ => Err(error()),
+        }
+    }
+
+    pub fn not(&self) -> Result<Value, Error> {
+        match self {
+            Value::Bool(b) => Ok(Value::Bool(!b)),
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Bool,
+                found: self.get_type(),
             })),
         }
     }
 
     pub fn eq(&self, other: &Value) -> Result<Value, Error> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a == b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a == b)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (a, b) = self.promote(other).unwrap();
+                Ok(Value::Bool(a == b))
+            },
             (Value::String(a), Value::String(b)) => Ok(Value::Bool(a == b)),
             (Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(a == b)),
+            (Value::List(a), Value::List(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                if a.len() != b.len() {
+                    return Ok(Value::Bool(false));
+                }
+                for (x, y) in a.iter().zip(b) {
+                    if x.eq(y)? == Value::Bool(false) {
+                        return Ok(Value::Bool(false));
+                    }
+                }
+                Ok(Value::Bool(true))
+            },
+            (Value::Map(a), Value::Map(b)) => {
+                if a.len() != b.len() {
+                    return Ok(Value::Bool(false));
+                }
+                for ((ka, va), (kb, vb)) in a.iter().zip(b) {
+                    if ka != kb || va.eq(vb)? == Value::Bool(false) {
+                        return Ok(Value::Bool(false));
+                    }
+                }
+                Ok(Value::Bool(true))
+            },
+            (Value::Range(a), Value::Range(b)) => Ok(Value::Bool(a == b)),
+            (Value::EnumCall { .. }, Value::EnumCall { .. }) => Ok(Value::Bool(self == other)),
+            (Value::Function { func: a, .. }, Value::Function { func: b, .. }) => Ok(Value::Bool(a == b)),
             _ => Err(Error::CannotCompare(CannotCompareError {
                 left: self.to_string(),
                 right: other.to_string(),
@@ -166,9 +422,19 @@ impl Value {
 
     pub fn neq(&self, other: &Value) -> Result<Value, Error> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a != b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Bool(a != b)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (a, b) = self.promote(other).unwrap();
+                Ok(Value::Bool(a != b))
+            },
             (Value::String(a), Value::String(b)) => Ok(Value::Bool(a != b)),
             (Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(a != b)),
+            (Value::List(_), Value::List(_))
+            | (Value::Tuple(_), Value::Tuple(_))
+            | (Value::Map(_), Value::Map(_))
+            | (Value::Range(_), Value::Range(_))
+            | (Value::EnumCall { .. }, Value::EnumCall { .. })
+            | (Value::Function { .. }, Value::Function { .. }) => self.eq(other)?.not(),
             _ => Err(Error::CannotCompare(CannotCompareError {
                 left: self.to_string(),
                 right: other.to_string(),
@@ -176,29 +442,110 @@ impl Value {
         }
     }
 
-    pub fn gt(&self, other: &Value) -> Result<Value, Error> {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(*a > *b)),
-            _ => Err(Error::CannotCompare(CannotCompareError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
+    /// A copy sharing nothing mutable with `self`. Values own their lists,
+    /// maps and structs outright, so the copy is a plain walk that always
+    /// terminates; only a function's code, which never changes, stays shared.
+    /// Keep this walk in step with any reference-counted value added later,
+    /// which would need to remember the copies it has made.
+    pub fn deep_clone(&self) -> Value {
+        match self {
+            Value::List(items) => Value::List(items.iter().map(Value::deep_clone).collect()),
+            Value::Tuple(items) => Value::Tuple(items.iter().map(Value::deep_clone).collect()),
+            Value::Map(map) => Value::Map(map.iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect()),
+            Value::CallStruct { name, fields } => Value::CallStruct {
+                name: name.clone(),
+                fields: fields.iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect(),
+            },
+            Value::DefStruct { name, fields, function } => Value::DefStruct {
+                name: name.clone(),
+                fields: fields.clone(),
+                function: function.iter().map(|(k, v)| (k.clone(), v.deep_clone())).collect(),
+            },
+            other => other.clone(),
         }
     }
 
-    pub fn lt(&self, other: &Value) -> Result<Value, Error> {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(*a < *b)),
-            _ => Err(Error::CannotCompare(CannotCompareError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
+    /// Converts to `to` as a `to` expression does, parsing strings into
+    /// numbers and truncating floats into ints.
+    pub fn cast(&self, to: &Type) -> Result<Value, Error> {
+        match to {
+            Type::Int => {
+                match self {
+                    Value::Int(i) => Ok(Value::Int(*i)),
+                    Value::Float(f) => Ok(Value::Int(*f as i64)),
+                    Value::String(s) => {
+                        Ok(Value::Int(match s.parse::<i64>() {
+                            Ok(i) => i,
+                            Err(_) => {
+                                return Err(Error::InvalidCastNumber(InvalidCastNumberError {
+                                    elt: s.clone()
+                                }))
+                            }
+                        }))
+                    },
+                    _ => Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::Int,
+                        found: self.get_type(),
+                    })),
+                }
+            },
+            Type::Float => {
+                match self {
+                    Value::Int(i) => Ok(Value::Float(*i as f64)),
+                    Value::Float(f) => Ok(Value::Float(*f)),
+                    Value::String(s) => {
+                        Ok(Value::Float(match s.parse::<f64>() {
+                            Ok(f) => f,
+                            Err(_) => {
+                                return Err(Error::InvalidCastNumber(InvalidCastNumberError {
+                                    elt: s.clone()
+                                }))
+                            }
+                        }))
+                    },
+                    _ => Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::Float,
+                        found: self.get_type(),
+                    })),
+                }
+            },
+            Type::String => {
+                match self {
+                    Value::String(s) => Ok(Value::String(s.clone())),
+                    Value::Int(i) => Ok(Value::String(i.to_string())),
+                    Value::Float(_) => Ok(Value::String(self.display_value())),
+                    _ => Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::None,
+                        found: self.get_type(),
+                    })),
+                }
+            },
+            Type::Bool => {
+                match self {
+                    Value::Bool(b) => Ok(Value::Bool(*b)),
+                    _ => Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::Bool,
+                        found: self.get_type(),
+                    })),
+                }
+            },
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::None,
+                found: self.get_type(),
+            })), 
         }
     }
 
-    pub fn ge(&self, other: &Value) -> Result<Value, Error> {
+    /// Orders numbers, promoting mixed ints and floats, and strings. `None`
+    /// when a float is NaN; other pairs of types cannot be compared.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, Error> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(*a >= *b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Some(a.cmp(b))),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (a, b) = self.promote(other).unwrap();
+                Ok(a.partial_cmp(&b))
+            },
+            (Value::String(a), Value::String(b)) => Ok(Some(a.cmp(b))),
             _ => Err(Error::CannotCompare(CannotCompareError {
                 left: self.to_string(),
                 right: other.to_string(),
@@ -206,14 +553,20 @@ impl Value {
         }
     }
 
+    pub fn gt(&self, other: &Value) -> Result<Value, Error> {
+        Ok(Value::Bool(self.compare(other)? == Some(Ordering::Greater)))
+    }
+
+    pub fn lt(&self, other: &Value) -> Result<Value, Error> {
+        Ok(Value::Bool(self.compare(other)? == Some(Ordering::Less)))
+    }
+
+    pub fn ge(&self, other: &Value) -> Result<Value, Error> {
+        Ok(Value::Bool(matches!(self.compare(other)?, Some(Ordering::Greater | Ordering::Equal))))
+    }
+
     pub fn le(&self, other: &Value) -> Result<Value, Error> {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(*a <= *b)),
-            _ => Err(Error::CannotCompare(CannotCompareError {
-                left: self.to_string(),
-                right: other.to_string(),
-            })),
-        }
+        Ok(Value::Bool(matches!(self.compare(other)?, Some(Ordering::Less | Ordering::Equal))))
     }
 
     pub fn and(&self, other: &Value) -> Result<Value, Error> {
@@ -236,9 +589,70 @@ impl Value {
         }
     }
 
+    /// The integer this value holds, for operations that need a whole number.
+    /// Floats are rejected even when integral rather than being truncated.
+    pub fn as_int(&self) -> Result<i64, Error> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Int,
+                found: self.get_type(),
+            })),
+        }
+    }
+
+    /// Like `as_int`, for counts and positions that cannot be negative. A
+    /// negative value is reported as the `arg` argument of `name`.
+    pub fn as_index(&self, name: &str, arg: &str) -> Result<usize, Error> {
+        let n = self.as_int()?;
+        usize::try_from(n).map_err(|_| Error::InvalidArgument(InvalidArgumentError {
+            name: name.to_string(),
+            reason: format!("{} must not be negative, found {}", arg, n),
+        }))
+    }
+
+    pub fn repeat_length(&self, other: &Value) -> Option<usize> {
+        match (self, other) {
+            (Value::String(s), n) | (n, Value::String(s)) => {
+                n.as_index("*", "count").ok().map(|n| s.len().saturating_mul(n))
+            },
+            _ => None,
+        }
+    }
+
+    /// `None`, `false`, zero, and empty strings, lists, maps and ranges are falsy;
+    /// every other value is truthy.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::Float(n) => *n != 0.0,
+            Value::String(s) => !s.is_empty(),
+            Value::List(l) => !l.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+            Value::Range(r) => range_len(r) != 0,
+            Value::None => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        match self {
+            Value::String(s) => Ok(s.is_empty()),
+            Value::List(l) => Ok(l.is_empty()),
+            Value::Map(m) => Ok(m.is_empty()),
+            Value::Range(r) => Ok(range_len(r) == 0),
+            _ => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: self.get_type(),
+            })),
+        }
+    }
+
     pub fn display_value(&self) -> String {
         match self {
-            Value::Number(n) => n.to_string(),
+            Value::Int(n) => n.to_string(),
+            Value::Float(n) => format!("{:?}", n),
             Value::String(s) => s.clone(),
             Value::Bool(b) => b.to_string(),
             Value::Function { .. } => "function".to_string(),
@@ -254,23 +668,150 @@ impl Value {
                 s.push_str("]");
                 s
             }
-            Value::Range(_) => "range".to_string(),
+            Value::Tuple(items) => format!(
+                "({})",
+                items.iter().map(|v| v.display_value()).collect::<Vec<String>>().join(", ")
+            ),
+            Value::Map(map) => format!(
+                "{{{}}}",
+                map.iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_value().display_value(), v.display_value()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Value::Range(r) => format!("{}..{}", r.start, r.end),
+            Value::None => "None".to_string(),
+            Value::DefStruct { name, fields, .. } => format!(
+                "struct {} {{ {} }}",
+                name,
+                fields.iter().map(|Ident(f)| f.clone()).collect::<Vec<String>>().join(", ")
+            ),
+            Value::CallStruct { name, fields } => {
+                let mut fields = fields
+                    .iter()
+                    .map(|(Ident(k), v)| format!("{}: {}", k, v.display_value()))
+                    .collect::<Vec<String>>();
+                fields.sort();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            Value::Enum { variants } => format!("enum {{ {} }}", variants.join(", ")),
+            Value::EnumCall { name, field } => format!("{}::{}", name, field),
+
+        }
+    }
+
+    /// Like `display_value`, with the numbers inside rendered per `format`.
+    pub fn display_with(&self, format: DisplayFormat) -> String {
+        let format_items = DisplayFormat { empty_none: false, ..format };
+        let join = |items: &[Value]| items.iter().map(|v| v.display_with(format_items)).collect::<Vec<String>>().join(", ");
+        match self {
+            Value::None if format.empty_none => String::new(),
+            Value::Int(n) => {
+                let digits = n.unsigned_abs().to_string();
+                let sign = if *n < 0 { "-" } else { "" };
+                format!("{}{}", sign, group_digits(&digits, format.thousands_separator))
+            }
+            Value::Float(n) if n.is_finite() => {
+                let s = match format.precision {
+                    Some(precision) => format!("{:.*}", precision, n.abs()),
+                    None => format!("{:?}", n.abs()),
+                };
+                let (int, frac) = s.split_at(s.find('.').unwrap_or(s.len()));
+                let sign = if n.is_sign_negative() && *n != 0. { "-" } else { "" };
+                format!("{}{}{}", sign, group_digits(int, format.thousands_separator), frac)
+            }
+            Value::List(list) => format!("[{}]", join(list)),
+            Value::Tuple(items) => format!("({})", join(items)),
+            Value::Map(map) => format!(
+                "{{{}}}",
+                map.iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_value().display_with(format_items), v.display_with(format_items)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            _ => self.display_value(),
+        }
+    }
+
+    pub fn debug_repr(&self) -> String {
+        match self {
+            Value::Int(n) => format!("Int({})", n),
+            Value::Float(n) => format!("Float({:?})", n),
+            Value::String(s) => format!("String({:?})", s),
+            Value::Bool(b) => format!("Bool({})", b),
+            Value::Function { name, .. } => format!("Function({})", name),
+            Value::List(list) => format!(
+                "List[{}]",
+                list.iter().map(|v| v.debug_repr()).collect::<Vec<String>>().join(", ")
+            ),
+            Value::Tuple(items) => format!(
+                "Tuple({})",
+                items.iter().map(|v| v.debug_repr()).collect::<Vec<String>>().join(", ")
+            ),
+            Value::Map(map) => format!(
+                "Map{{{}}}",
+                map.iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_value().debug_repr(), v.debug_repr()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Value::Range(r) => format!("Range({}..{})", r.start, r.end),
+            Value::DefStruct { name, .. } => format!("DefStruct({})", name),
+            Value::CallStruct { name, fields } => {
+                let mut fields = fields
+                    .iter()
+                    .map(|(Ident(k), v)| format!("{}: {}", k, v.debug_repr()))
+                    .collect::<Vec<String>>();
+                fields.sort();
+                format!("{} {{ {} }}", name, fields.join(", "))
+            }
+            Value::Enum { variants } => format!("Enum[{}]", variants.join(", ")),
+            Value::EnumCall { name, field } => format!("EnumCall({}::{})", name, field),
             Value::None => "None".to_string(),
-            Value::DefStruct { .. } => todo!(),
-            Value::CallStruct { .. } => todo!(),
-            Value::Enum { .. } => todo!(),
-            Value::EnumCall { .. } => todo!()
+        }
+    }
 
+    /// Like `get_type`, but also describes list elements and struct fields.
+    /// Empty lists have an unknown element type; heterogeneous ones a mixed one.
+    pub fn type_info(&self) -> TypeInfo {
+        match self {
+            Value::List(items) => {
+                let mut infos = items.iter().map(|v| v.type_info());
+                let element = match infos.next() {
+                    Some(first) => {
+                        if infos.all(|info| info == first) {
+                            first
+                        } else {
+                            TypeInfo::Mixed
+                        }
+                    }
+                    None => TypeInfo::Unknown,
+                };
+                TypeInfo::List(Box::new(element))
+            }
+            Value::Tuple(items) => TypeInfo::Tuple(items.iter().map(|v| v.type_info()).collect()),
+            Value::CallStruct { name, fields } => {
+                let mut fields = fields
+                    .iter()
+                    .map(|(Ident(k), v)| (k.clone(), v.type_info()))
+                    .collect::<Vec<(String, TypeInfo)>>();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                TypeInfo::Struct { name: name.clone(), fields }
+            }
+            _ => TypeInfo::Simple(self.get_type()),
         }
     }
 
     pub fn get_type(&self) -> Type {
         match self {
-            Value::Number(_) => Type::Int,
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
             Value::String(_) => Type::String,
             Value::Bool(_) => Type::Bool,
             Value::Function { .. } => Type::Func,
             Value::List(_) => Type::List,
+            Value::Tuple(_) => Type::Tuple,
+            Value::Map(_) => Type::Map,
             Value::Range(_) => Type::Range,
             Value::CallStruct { name , ..} => Type::FieldStruct(name.clone()),
             Value::DefStruct { name, .. } => Type::Struct(name.clone()),
@@ -288,3 +829,17 @@ impl fmt::Display for Value {
         write!(f, "{}", self.display_value())
     }
 }
+
+fn group_digits(digits: &str, separate: bool) -> String {
+    if !separate || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return digits.to_string();
+    }
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
@@ -0,0 +1,422 @@
+use std::collections::VecDeque;
+
+use crate::tree::Expr;
+use crate::tree::Op;
+use crate::tree::IOp;
+use crate::tree::UnaryOp;
+use crate::tree::Literal;
+use crate::executer::value::Type;
+use crate::errors::Error;
+use crate::errors::ParseError;
+
+const INDENT: &str = "    ";
+
+/// Parses `src` and prints it back in canonical form: one statement per
+/// line, four spaces per block level, and only the parentheses the grammar
+/// needs. The result parses to the same tree as `src`.
+pub fn format_source(src: &str) -> Result<String, Error> {
+    let parse = |src: &str| crate::tlang::ExprsParser::new().parse(src).map_err(|e| e.to_string());
+    let (mut comments, marked) = comments(src);
+    // Block comments parse to `Expr::Empty`, but line comments are skipped,
+    // so they are first parsed as block comments to find where they go.
+    // Where that does not parse, the line comments are dropped.
+    let program = match parse(&marked) {
+        Ok(program) => program,
+        Err(_) => {
+            comments.retain(|c| matches!(c, Comment::Block(_)));
+            parse(src).map_err(|message| Error::Parse(ParseError { message }))?
+        }
+    };
+    let mut formatter = Formatter { comments, depth: 0 };
+    let body = match program {
+        Expr::Block { body } => body,
+        expr => vec![expr],
+    };
+    let mut out = String::new();
+    for stmt in &body {
+        out.push_str(formatter.at(stmt, Pos::Stmt).trim_end());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+enum Comment {
+    Block(String),
+    Line(String),
+}
+
+// The comments of `src` in order, and `src` with each line comment moved to
+// a line of its own as a block comment. Like the lexer, a block comment runs
+// to the last `*/` on its line, and neither kind starts inside a string.
+fn comments(src: &str) -> (VecDeque<Comment>, String) {
+    let mut comments = VecDeque::new();
+    let mut marked = String::with_capacity(src.len());
+    let mut rest = src;
+    while let Some(start) = rest.find(['\'', '/']) {
+        marked.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let line = rest.split('\n').next().unwrap_or("");
+        let end = if rest.starts_with('\'') {
+            let mut chars = rest.char_indices().skip(1);
+            let mut end = rest.len();
+            while let Some((i, c)) = chars.next() {
+                if c == '\\' {
+                    chars.next();
+                } else if c == '\'' {
+                    end = i + 1;
+                    break;
+                }
+            }
+            end
+        } else if rest.starts_with("//") {
+            comments.push_back(Comment::Line(line.trim_end().to_string()));
+            marked.push_str(&format!("\n/*{}*/", line[2..].replace("*/", "* /")));
+            rest = &rest[line.len()..];
+            continue;
+        } else {
+            match line.rfind("*/") {
+                Some(end) if rest.starts_with("/*") && end >= 2 => {
+                    comments.push_back(Comment::Block(line[..end + 2].to_string()));
+                    end + 2
+                }
+                _ => 1,
+            }
+        };
+        marked.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+    marked.push_str(rest);
+    (comments, marked)
+}
+
+// The grammar positions an expression can be printed in, from the loosest
+// to the tightest.
+#[derive(Clone, Copy)]
+enum Pos {
+    // Like `Expr`, but a statement cannot start with a unary minus.
+    Stmt,
+    Expr,
+    Or,
+    And,
+    Operand,
+    Sum,
+    Fact,
+    Unary,
+    Value,
+    Postfix,
+    Key,
+}
+
+fn is_cmp(op: &Op) -> bool {
+    matches!(op, Op::Eq | Op::Neq | Op::Lt | Op::Le | Op::Gt | Op::Ge)
+}
+
+fn fits(expr: &Expr, pos: Pos) -> bool {
+    match pos {
+        Pos::Stmt => !starts_with_neg(expr),
+        Pos::Expr => true,
+        Pos::Or => matches!(expr, Expr::BinOp { op: Op::Or, .. }) || fits(expr, Pos::And),
+        Pos::And => matches!(expr, Expr::BinOp { op: Op::And, .. }) || fits(expr, Pos::Operand),
+        Pos::Operand => matches!(expr, Expr::BinOp { op, .. } if is_cmp(op)) || fits(expr, Pos::Value),
+        Pos::Sum => matches!(expr, Expr::BinOp { op: Op::Add | Op::Sub, .. }) || fits(expr, Pos::Fact),
+        Pos::Fact => matches!(expr, Expr::BinOp { op: Op::Mul | Op::Div | Op::Mod, .. }) || fits(expr, Pos::Unary),
+        Pos::Unary => matches!(expr, Expr::UnaryOp { .. } | Expr::BinOp { op: Op::Pow, .. }) || fits(expr, Pos::Value),
+        Pos::Value => matches!(expr, Expr::Literal { .. } | Expr::Range { .. } | Expr::GetAttr { .. }) || fits(expr, Pos::Postfix),
+        Pos::Postfix => matches!(
+            expr,
+            Expr::Ident { .. } | Expr::Call { .. } | Expr::Apply { .. } | Expr::List { .. } | Expr::Tuple { .. } | Expr::Map { .. } | Expr::Index { .. }
+        ),
+        Pos::Key => matches!(
+            expr,
+            Expr::Literal { value: Literal::Int(_) } | Expr::Ident { .. } | Expr::Call { .. } | Expr::GetAttr { .. }
+        ),
+    }
+}
+
+fn starts_with_neg(expr: &Expr) -> bool {
+    match expr {
+        Expr::UnaryOp { op: UnaryOp::Neg, .. } => true,
+        Expr::BinOp { op: Op::Add | Op::Sub, left, .. } => fits(left, Pos::Sum) && starts_with_neg(left),
+        Expr::BinOp { op: Op::Mul | Op::Div | Op::Mod, left, .. } => fits(left, Pos::Fact) && starts_with_neg(left),
+        _ => false,
+    }
+}
+
+fn literal(value: &Literal) -> String {
+    match value {
+        Literal::Int(n) => n.to_string(),
+        Literal::Float(n) => {
+            let s = n.to_string();
+            if s.contains('.') {
+                s
+            } else {
+                format!("{}.0", s)
+            }
+        }
+        Literal::String(s) => {
+            let mut out = String::from("'");
+            for c in s.chars() {
+                match c {
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    '\r' => out.push_str("\\r"),
+                    '\\' => out.push_str("\\\\"),
+                    '\'' => out.push_str("\\'"),
+                    c => out.push(c),
+                }
+            }
+            out.push('\'');
+            out
+        }
+        Literal::Bool(b) => b.to_string(),
+    }
+}
+
+fn binary_op(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::Pow => "**",
+        Op::Eq => "==",
+        Op::Neq => "!=",
+        Op::Lt => "<",
+        Op::Gt => ">",
+        Op::Le => "<=",
+        Op::Ge => ">=",
+        Op::And => "&&",
+        Op::Or => "||",
+    }
+}
+
+struct Formatter {
+    comments: VecDeque<Comment>,
+    depth: usize,
+}
+
+impl Formatter {
+    // Prints `expr`, in parentheses when it cannot stand at `pos`.
+    fn at(&mut self, expr: &Expr, pos: Pos) -> String {
+        let s = self.expr(expr);
+        if fits(expr, pos) {
+            s
+        } else if let Expr::Block { .. } = expr {
+            format!("do {}", s)
+        } else {
+            format!("({})", s)
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expr], separator: &str) -> String {
+        exprs.iter().map(|e| self.at(e, Pos::Expr)).collect::<Vec<String>>().join(separator)
+    }
+
+    fn binding(&mut self, name: &str, type_: &Option<Type>, value: &Expr) -> String {
+        match type_ {
+            Some(t) => format!("{}: {} = {}", name, t.name(), self.at(value, Pos::Expr)),
+            None => format!("{} = {}", name, self.at(value, Pos::Expr)),
+        }
+    }
+
+    fn block(&mut self, expr: &Expr) -> String {
+        let body = match expr {
+            Expr::Block { body } => body.as_slice(),
+            expr => std::slice::from_ref(expr),
+        };
+        if body.is_empty() {
+            return "{}".to_string();
+        }
+        self.depth += 1;
+        let mut out = String::from("{\n");
+        for stmt in body {
+            out.push_str(&INDENT.repeat(self.depth));
+            out.push_str(self.at(stmt, Pos::Stmt).trim_end());
+            out.push('\n');
+        }
+        self.depth -= 1;
+        out.push_str(&INDENT.repeat(self.depth));
+        out.push('}');
+        out
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal { value } => literal(value),
+            Expr::Ident { ident } => ident.clone(),
+            // A line comment ends its line, so whatever follows goes on the next.
+            Expr::Empty => match self.comments.pop_front() {
+                Some(Comment::Block(text)) => text,
+                Some(Comment::Line(text)) => format!("{}\n{}", text, INDENT.repeat(self.depth)),
+                None => "/**/".to_string(),
+            },
+            Expr::Block { .. } => self.block(expr),
+            Expr::IfThen { cond, then } => {
+                format!("if {} {}", self.at(cond, Pos::Expr), self.block(then))
+            }
+            Expr::IfThenElse { cond, then, else_ } => {
+                let head = format!("if {} {}", self.at(cond, Pos::Expr), self.block(then));
+                match **else_ {
+                    Expr::IfThen { .. } | Expr::IfThenElse { .. } => format!("{} else {}", head, self.expr(else_)),
+                    _ => format!("{} else {}", head, self.block(else_)),
+                }
+            }
+            Expr::While { cond, body } => format!("while {} {}", self.at(cond, Pos::Expr), self.block(body)),
+            Expr::For { name, iter, body } => format!(
+                "for {} in {} {}",
+                self.at(name, Pos::Expr),
+                self.at(iter, Pos::Expr),
+                self.block(body)
+            ),
+            Expr::Assign { name, value, mutable, type_ } => {
+                format!("let {}{}", if *mutable { "mut " } else { "" }, self.binding(name, type_, value))
+            }
+            Expr::AssignMany { assigns } => {
+                let mut mutable = false;
+                let mut bindings = Vec::new();
+                for assign in assigns {
+                    if let Expr::Assign { name, value, mutable: m, type_ } = assign {
+                        mutable = *m;
+                        bindings.push(self.binding(name, type_, value));
+                    }
+                }
+                format!("let {}{}", if mutable { "mut " } else { "" }, bindings.join(", "))
+            }
+            Expr::AssignTuple { names, value, mutable } => format!(
+                "let {}({}) = {}",
+                if *mutable { "mut " } else { "" },
+                names.join(", "),
+                self.at(value, Pos::Expr)
+            ),
+            Expr::IOp { op, value, name } => format!(
+                "{} {} {}",
+                name,
+                match op {
+                    IOp::IAdd => "+=",
+                    IOp::ISub => "-=",
+                    IOp::IMul => "*=",
+                    IOp::IDiv => "/=",
+                },
+                self.at(value, Pos::Expr)
+            ),
+            Expr::SetVar { name, value } => format!("{} := {}", name, self.at(value, Pos::Expr)),
+            Expr::SetAttr { name, attr, value } => format!("{}->{} := {}", name, attr, self.at(value, Pos::Expr)),
+            Expr::SetIndex { name, index, value } => {
+                format!("{}.{} := {}", name, self.at(index, Pos::Key), self.at(value, Pos::Expr))
+            }
+            Expr::Swap { a, b } => format!("swap({}, {})", a, b),
+            Expr::BinOp { op, left, right } => {
+                let (left_pos, right_pos) = match op {
+                    Op::Add | Op::Sub => (Pos::Sum, Pos::Fact),
+                    Op::Mul | Op::Div | Op::Mod => (Pos::Fact, Pos::Unary),
+                    Op::Pow => (Pos::Value, Pos::Unary),
+                    Op::And => (Pos::And, Pos::Operand),
+                    Op::Or => (Pos::Or, Pos::And),
+                    _ => (Pos::Value, Pos::Value),
+                };
+                format!("{} {} {}", self.at(left, left_pos), binary_op(op), self.at(right, right_pos))
+            }
+            Expr::UnaryOp { op, value } => format!(
+                "{}{}",
+                match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                },
+                self.at(value, Pos::Unary)
+            ),
+            Expr::CustomOp { op, left, right } => {
+                format!("{} `{}` {}", self.at(left, Pos::Value), op, self.at(right, Pos::Value))
+            }
+            Expr::FunDef { name, args, body } => {
+                format!("def {}({}) {}", name, self.list(args, ", "), self.block(body))
+            }
+            Expr::Impl { name_struct, name_method, args, body } => format!(
+                "impl {} def {}({}) {}",
+                name_struct,
+                name_method,
+                self.list(args, ", "),
+                self.block(body)
+            ),
+            Expr::Call { name, args } => format!("@{}({})", name, self.list(args, ", ")),
+            Expr::Lambda { args, body } => format!("fn({}) => {}", self.list(args, ", "), self.at(body, Pos::Expr)),
+            Expr::Apply { func, args } => format!("@({})({})", self.at(func, Pos::Expr), self.list(args, ", ")),
+            Expr::List { elems } => format!("[{}]", self.list(elems, ", ")),
+            Expr::Spread { value } => format!("...{}", self.at(value, Pos::Expr)),
+            Expr::Tuple { elems } => match elems.as_slice() {
+                [elem] => format!("({},)", self.at(elem, Pos::Expr)),
+                elems => format!("({})", self.list(elems, ", ")),
+            },
+            Expr::Map { entries } => {
+                if entries.is_empty() {
+                    return "#{}".to_string();
+                }
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| format!("{} => {}", self.at(k, Pos::Expr), self.at(v, Pos::Expr)))
+                    .collect::<Vec<String>>();
+                format!("#{{ {} }}", entries.join(", "))
+            }
+            Expr::Index { name, index } => {
+                // `m.0.1` would lex its key as a float.
+                let key = match (&**name, &**index) {
+                    (Expr::Index { index: inner, .. }, Expr::Literal { value: Literal::Int(n) })
+                        if matches!(**inner, Expr::Literal { value: Literal::Int(_) }) => format!("({})", n),
+                    _ => self.at(index, Pos::Key),
+                };
+                format!("{}.{}", self.at(name, Pos::Postfix), key)
+            }
+            Expr::Range { start, end } => format!("{}:{}", self.at(start, Pos::Value), self.at(end, Pos::Value)),
+            Expr::StructDef { name, fields } => format!("struct {} {{ {} }}", name, self.list(fields, "; ")),
+            Expr::CallStruct { name, base, args } => {
+                let mut fields = Vec::new();
+                if let Some(base) = base {
+                    fields.push(format!("...{}", self.at(base, Pos::Expr)));
+                }
+                for (field, value) in args {
+                    fields.push(format!("{} => {}", self.expr(field), self.at(value, Pos::Expr)));
+                }
+                if fields.is_empty() {
+                    format!("@{}{{}}", name)
+                } else {
+                    format!("@{}{{ {} }}", name, fields.join("; "))
+                }
+            }
+            Expr::GetAttr { name, attr, optional } => {
+                format!("{}{}{}", name, if *optional { "?->" } else { "->" }, attr)
+            }
+            Expr::GetFunc { name, func, args, optional } => format!(
+                "{}{}@{}({})",
+                name,
+                if *optional { "?->" } else { "->" },
+                func,
+                self.list(args, ", ")
+            ),
+            Expr::Match { value, cases } => {
+                let value = self.at(value, Pos::Expr);
+                self.depth += 1;
+                let mut out = format!("match {} {{\n", value);
+                for (pattern, result) in cases {
+                    let case = format!("{} => {}", self.at(pattern, Pos::Expr), self.at(result, Pos::Expr));
+                    out.push_str(&format!("{}{},\n", INDENT.repeat(self.depth), case));
+                }
+                self.depth -= 1;
+                out.push_str(&INDENT.repeat(self.depth));
+                out.push('}');
+                out
+            }
+            Expr::Enum { name, fields } => format!("enum {} {{ {} }}", name, fields.join("; ")),
+            Expr::EnumCall { name, field } => format!("@{}{{{}}}", name, field),
+            Expr::To { value, to } => format!("{} to {}", self.at(value, Pos::Value), to.name()),
+            Expr::TryCatch { body, name, handler } => {
+                format!("try {} catch {} {}", self.block(body), name, self.block(handler))
+            }
+            Expr::With { name, value, body } => {
+                format!("with {} = {} {}", name, self.at(value, Pos::Expr), self.block(body))
+            }
+            Expr::Break => "break".to_string(),
+            Expr::Continue => "continue".to_string(),
+            Expr::Return { value } => format!("return {}", self.at(value, Pos::Expr)),
+        }
+    }
+}
@@ -5,6 +5,8 @@ mod errors;
 mod tree;
 mod test;
 mod std_t;
+mod serialize;
+mod format;
 use lalrpop_util::lalrpop_mod;
 use std::fs;
 
@@ -0,0 +1,611 @@
+use crate::tree::Expr;
+use crate::tree::Op;
+use crate::tree::IOp;
+use crate::tree::UnaryOp;
+use crate::tree::Literal;
+use crate::executer::value::Type;
+use crate::errors::Error;
+use crate::errors::ParseError;
+
+// Bumped whenever the encoding changes, so stale caches are rejected
+// instead of being decoded into the wrong tree.
+const FORMAT_VERSION: u8 = 1;
+
+// Decoding recurses once per nesting level, so untrusted input must not be
+// able to nest deeper than the stack allows.
+const MAX_DEPTH: usize = 128;
+
+pub fn serialize_ast(expr: &Expr) -> Vec<u8> {
+    let mut out = vec![FORMAT_VERSION];
+    write_expr(&mut out, expr);
+    out
+}
+
+pub fn deserialize_ast(bytes: &[u8]) -> Result<Expr, Error> {
+    let mut reader = Reader { bytes, pos: 0, depth: 0 };
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!("unsupported AST format version {}", version)));
+    }
+    let expr = reader.expr()?;
+    if reader.pos != bytes.len() {
+        return Err(invalid(format!("{} trailing bytes", bytes.len() - reader.pos)));
+    }
+    Ok(expr)
+}
+
+fn invalid(message: String) -> Error {
+    Error::Parse(ParseError { message })
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_strs(out: &mut Vec<u8>, items: &[String]) {
+    write_u32(out, items.len());
+    for item in items {
+        write_str(out, item);
+    }
+}
+
+fn write_exprs(out: &mut Vec<u8>, items: &[Expr]) {
+    write_u32(out, items.len());
+    for item in items {
+        write_expr(out, item);
+    }
+}
+
+fn write_pairs(out: &mut Vec<u8>, items: &[(Expr, Expr)]) {
+    write_u32(out, items.len());
+    for (a, b) in items {
+        write_expr(out, a);
+        write_expr(out, b);
+    }
+}
+
+fn write_type(out: &mut Vec<u8>, type_: &Type) {
+    match type_ {
+        Type::Int => out.push(0),
+        Type::Float => out.push(1),
+        Type::String => out.push(2),
+        Type::Bool => out.push(3),
+        Type::List => out.push(4),
+        Type::Tuple => out.push(5),
+        Type::Func => out.push(6),
+        Type::Range => out.push(7),
+        Type::Enum => out.push(8),
+        Type::FieldEnum(name) => {
+            out.push(9);
+            write_str(out, name);
+        }
+        Type::Struct(name) => {
+            out.push(10);
+            write_str(out, name);
+        }
+        Type::FieldStruct(name) => {
+            out.push(11);
+            write_str(out, name);
+        }
+        Type::None => out.push(12),
+        Type::Map => out.push(13),
+    }
+}
+
+fn write_op(out: &mut Vec<u8>, op: &Op) {
+    out.push(match op {
+        Op::Add => 0,
+        Op::Sub => 1,
+        Op::Mul => 2,
+        Op::Div => 3,
+        Op::Mod => 4,
+        Op::Pow => 5,
+        Op::Eq => 6,
+        Op::Neq => 7,
+        Op::Lt => 8,
+        Op::Gt => 9,
+        Op::Le => 10,
+        Op::Ge => 11,
+        Op::And => 12,
+        Op::Or => 13,
+    });
+}
+
+fn write_expr(out: &mut Vec<u8>, expr: &Expr) {
+    match expr {
+        Expr::IfThen { cond, then } => {
+            out.push(0);
+            write_expr(out, cond);
+            write_expr(out, then);
+        }
+        Expr::IfThenElse { cond, then, else_ } => {
+            out.push(1);
+            write_expr(out, cond);
+            write_expr(out, then);
+            write_expr(out, else_);
+        }
+        Expr::While { cond, body } => {
+            out.push(2);
+            write_expr(out, cond);
+            write_expr(out, body);
+        }
+        Expr::Assign { name, value, mutable, type_ } => {
+            out.push(3);
+            write_str(out, name);
+            write_expr(out, value);
+            out.push(*mutable as u8);
+            match type_ {
+                Some(t) => {
+                    out.push(1);
+                    write_type(out, t);
+                }
+                None => out.push(0),
+            }
+        }
+        Expr::AssignTuple { names, value, mutable } => {
+            out.push(4);
+            write_strs(out, names);
+            write_expr(out, value);
+            out.push(*mutable as u8);
+        }
+        Expr::Literal { value } => {
+            out.push(5);
+            match value {
+                Literal::Int(n) => {
+                    out.push(0);
+                    out.extend_from_slice(&n.to_le_bytes());
+                }
+                Literal::Float(n) => {
+                    out.push(1);
+                    out.extend_from_slice(&n.to_le_bytes());
+                }
+                Literal::String(s) => {
+                    out.push(2);
+                    write_str(out, s);
+                }
+                Literal::Bool(b) => {
+                    out.push(3);
+                    out.push(*b as u8);
+                }
+            }
+        }
+        Expr::BinOp { op, left, right } => {
+            out.push(6);
+            write_op(out, op);
+            write_expr(out, left);
+            write_expr(out, right);
+        }
+        Expr::UnaryOp { op, value } => {
+            out.push(7);
+            out.push(match op {
+                UnaryOp::Neg => 0,
+                UnaryOp::Not => 1,
+            });
+            write_expr(out, value);
+        }
+        Expr::CustomOp { op, left, right } => {
+            out.push(8);
+            write_str(out, op);
+            write_expr(out, left);
+            write_expr(out, right);
+        }
+        Expr::IOp { op, value, name } => {
+            out.push(9);
+            out.push(match op {
+                IOp::IAdd => 0,
+                IOp::ISub => 1,
+                IOp::IMul => 2,
+                IOp::IDiv => 3,
+            });
+            write_expr(out, value);
+            write_str(out, name);
+        }
+        Expr::For { name, iter, body } => {
+            out.push(10);
+            write_expr(out, name);
+            write_expr(out, iter);
+            write_expr(out, body);
+        }
+        Expr::FunDef { name, args, body } => {
+            out.push(11);
+            write_str(out, name);
+            write_exprs(out, args);
+            write_expr(out, body);
+        }
+        Expr::Call { name, args } => {
+            out.push(12);
+            write_str(out, name);
+            write_exprs(out, args);
+        }
+        Expr::Block { body } => {
+            out.push(13);
+            write_exprs(out, body);
+        }
+        Expr::Ident { ident } => {
+            out.push(14);
+            write_str(out, ident);
+        }
+        Expr::List { elems } => {
+            out.push(15);
+            write_exprs(out, elems);
+        }
+        Expr::Index { name, index } => {
+            out.push(16);
+            write_expr(out, name);
+            write_expr(out, index);
+        }
+        Expr::Range { start, end } => {
+            out.push(17);
+            write_expr(out, start);
+            write_expr(out, end);
+        }
+        Expr::StructDef { name, fields } => {
+            out.push(18);
+            write_str(out, name);
+            write_exprs(out, fields);
+        }
+        Expr::CallStruct { name, base, args } => {
+            out.push(19);
+            write_str(out, name);
+            match base {
+                Some(base) => {
+                    out.push(1);
+                    write_expr(out, base);
+                }
+                None => out.push(0),
+            }
+            write_pairs(out, args);
+        }
+        Expr::GetAttr { name, attr, optional } => {
+            out.push(20);
+            write_str(out, name);
+            write_str(out, attr);
+            out.push(*optional as u8);
+        }
+        Expr::Impl { name_struct, name_method, args, body } => {
+            out.push(21);
+            write_str(out, name_struct);
+            write_str(out, name_method);
+            write_exprs(out, args);
+            write_expr(out, body);
+        }
+        Expr::GetFunc { name, func, args, optional } => {
+            out.push(22);
+            write_str(out, name);
+            write_str(out, func);
+            write_exprs(out, args);
+            out.push(*optional as u8);
+        }
+        Expr::SetVar { name, value } => {
+            out.push(23);
+            write_str(out, name);
+            write_expr(out, value);
+        }
+        Expr::Swap { a, b } => {
+            out.push(24);
+            write_str(out, a);
+            write_str(out, b);
+        }
+        Expr::Match { value, cases } => {
+            out.push(25);
+            write_expr(out, value);
+            write_pairs(out, cases);
+        }
+        Expr::Enum { name, fields } => {
+            out.push(26);
+            write_str(out, name);
+            write_strs(out, fields);
+        }
+        Expr::EnumCall { name, field } => {
+            out.push(27);
+            write_str(out, name);
+            write_str(out, field);
+        }
+        Expr::To { value, to } => {
+            out.push(28);
+            write_expr(out, value);
+            write_type(out, to);
+        }
+        Expr::TryCatch { body, name, handler } => {
+            out.push(29);
+            write_expr(out, body);
+            write_str(out, name);
+            write_expr(out, handler);
+        }
+        Expr::Break => out.push(30),
+        Expr::Continue => out.push(31),
+        Expr::Return { value } => {
+            out.push(32);
+            write_expr(out, value);
+        }
+        Expr::Spread { value } => {
+            out.push(33);
+            write_expr(out, value);
+        }
+        Expr::Empty => out.push(34),
+        Expr::Map { entries } => {
+            out.push(35);
+            write_pairs(out, entries);
+        }
+        Expr::Tuple { elems } => {
+            out.push(36);
+            write_exprs(out, elems);
+        }
+        Expr::AssignMany { assigns } => {
+            out.push(37);
+            write_exprs(out, assigns);
+        }
+        Expr::Lambda { args, body } => {
+            out.push(38);
+            write_exprs(out, args);
+            write_expr(out, body);
+        }
+        Expr::Apply { func, args } => {
+            out.push(39);
+            write_expr(out, func);
+            write_exprs(out, args);
+        }
+        Expr::SetIndex { name, index, value } => {
+            out.push(40);
+            write_str(out, name);
+            write_expr(out, index);
+            write_expr(out, value);
+        }
+        Expr::SetAttr { name, attr, value } => {
+            out.push(42);
+            write_str(out, name);
+            write_str(out, attr);
+            write_expr(out, value);
+        }
+        Expr::With { name, value, body } => {
+            out.push(41);
+            write_str(out, name);
+            write_expr(out, value);
+            write_expr(out, body);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.pos < n {
+            return Err(invalid("unexpected end of input".to_string()));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(invalid(format!("invalid bool {}", b))),
+        }
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn eight(&mut self) -> Result<[u8; 8], Error> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(buf)
+    }
+
+    fn str(&mut self) -> Result<String, Error> {
+        let len = self.u32()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn strs(&mut self) -> Result<Vec<String>, Error> {
+        let len = self.u32()?;
+        (0..len).map(|_| self.str()).collect()
+    }
+
+    fn boxed(&mut self) -> Result<Box<Expr>, Error> {
+        Ok(Box::new(self.expr()?))
+    }
+
+    fn exprs(&mut self) -> Result<Vec<Expr>, Error> {
+        let len = self.u32()?;
+        (0..len).map(|_| self.expr()).collect()
+    }
+
+    fn pairs(&mut self) -> Result<Vec<(Expr, Expr)>, Error> {
+        let len = self.u32()?;
+        (0..len).map(|_| Ok((self.expr()?, self.expr()?))).collect()
+    }
+
+    fn type_(&mut self) -> Result<Type, Error> {
+        Ok(match self.u8()? {
+            0 => Type::Int,
+            1 => Type::Float,
+            2 => Type::String,
+            3 => Type::Bool,
+            4 => Type::List,
+            5 => Type::Tuple,
+            6 => Type::Func,
+            7 => Type::Range,
+            8 => Type::Enum,
+            9 => Type::FieldEnum(self.str()?),
+            10 => Type::Struct(self.str()?),
+            11 => Type::FieldStruct(self.str()?),
+            12 => Type::None,
+            13 => Type::Map,
+            tag => return Err(invalid(format!("invalid type tag {}", tag))),
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, Error> {
+        Ok(match self.u8()? {
+            0 => Op::Add,
+            1 => Op::Sub,
+            2 => Op::Mul,
+            3 => Op::Div,
+            4 => Op::Mod,
+            5 => Op::Pow,
+            6 => Op::Eq,
+            7 => Op::Neq,
+            8 => Op::Lt,
+            9 => Op::Gt,
+            10 => Op::Le,
+            11 => Op::Ge,
+            12 => Op::And,
+            13 => Op::Or,
+            tag => return Err(invalid(format!("invalid operator tag {}", tag))),
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(invalid("AST nested too deeply".to_string()));
+        }
+        self.depth += 1;
+        let expr = self.node();
+        self.depth -= 1;
+        expr
+    }
+
+    // The tags are decoded in groups, because a single match over all of them
+    // needs a large frame in unoptimized builds, once per nesting level.
+    fn node(&mut self) -> Result<Expr, Error> {
+        match self.u8()? {
+            tag @ 0..=7 => self.nodes_0_to_7(tag),
+            tag @ 8..=15 => self.nodes_8_to_15(tag),
+            tag @ 16..=23 => self.nodes_16_to_23(tag),
+            tag @ 24..=31 => self.nodes_24_to_31(tag),
+            tag @ 32..=42 => self.nodes_32_to_42(tag),
+            tag => Err(invalid(format!("invalid expression tag {}", tag))),
+        }
+    }
+
+    fn nodes_0_to_7(&mut self, tag: u8) -> Result<Expr, Error> {
+        Ok(match tag {
+            0 => Expr::IfThen { cond: self.boxed()?, then: self.boxed()? },
+            1 => Expr::IfThenElse { cond: self.boxed()?, then: self.boxed()?, else_: self.boxed()? },
+            2 => Expr::While { cond: self.boxed()?, body: self.boxed()? },
+            3 => Expr::Assign {
+                name: self.str()?,
+                value: self.boxed()?,
+                mutable: self.bool()?,
+                type_: if self.bool()? { Some(self.type_()?) } else { None },
+            },
+            4 => Expr::AssignTuple { names: self.strs()?, value: self.boxed()?, mutable: self.bool()? },
+            5 => Expr::Literal {
+                value: match self.u8()? {
+                    0 => Literal::Int(i64::from_le_bytes(self.eight()?)),
+                    1 => Literal::Float(f64::from_le_bytes(self.eight()?)),
+                    2 => Literal::String(self.str()?),
+                    3 => Literal::Bool(self.bool()?),
+                    tag => return Err(invalid(format!("invalid literal tag {}", tag))),
+                },
+            },
+            6 => Expr::BinOp { op: self.op()?, left: self.boxed()?, right: self.boxed()? },
+            7 => Expr::UnaryOp {
+                op: match self.u8()? {
+                    0 => UnaryOp::Neg,
+                    1 => UnaryOp::Not,
+                    tag => return Err(invalid(format!("invalid unary operator tag {}", tag))),
+                },
+                value: self.boxed()?,
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    fn nodes_8_to_15(&mut self, tag: u8) -> Result<Expr, Error> {
+        Ok(match tag {
+            8 => Expr::CustomOp { op: self.str()?, left: self.boxed()?, right: self.boxed()? },
+            9 => Expr::IOp {
+                op: match self.u8()? {
+                    0 => IOp::IAdd,
+                    1 => IOp::ISub,
+                    2 => IOp::IMul,
+                    3 => IOp::IDiv,
+                    tag => return Err(invalid(format!("invalid assignment operator tag {}", tag))),
+                },
+                value: self.boxed()?,
+                name: self.str()?,
+            },
+            10 => Expr::For { name: self.boxed()?, iter: self.boxed()?, body: self.boxed()? },
+            11 => Expr::FunDef { name: self.str()?, args: self.exprs()?, body: self.boxed()? },
+            12 => Expr::Call { name: self.str()?, args: self.exprs()? },
+            13 => Expr::Block { body: self.exprs()? },
+            14 => Expr::Ident { ident: self.str()? },
+            15 => Expr::List { elems: self.exprs()? },
+            _ => unreachable!(),
+        })
+    }
+
+    fn nodes_16_to_23(&mut self, tag: u8) -> Result<Expr, Error> {
+        Ok(match tag {
+            16 => Expr::Index { name: self.boxed()?, index: self.boxed()? },
+            17 => Expr::Range { start: self.boxed()?, end: self.boxed()? },
+            18 => Expr::StructDef { name: self.str()?, fields: self.exprs()? },
+            19 => Expr::CallStruct {
+                name: self.str()?,
+                base: if self.bool()? { Some(self.boxed()?) } else { None },
+                args: self.pairs()?,
+            },
+            20 => Expr::GetAttr { name: self.str()?, attr: self.str()?, optional: self.bool()? },
+            21 => Expr::Impl {
+                name_struct: self.str()?,
+                name_method: self.str()?,
+                args: self.exprs()?,
+                body: self.boxed()?,
+            },
+            22 => Expr::GetFunc { name: self.str()?, func: self.str()?, args: self.exprs()?, optional: self.bool()? },
+            23 => Expr::SetVar { name: self.str()?, value: self.boxed()? },
+            _ => unreachable!(),
+        })
+    }
+
+    fn nodes_24_to_31(&mut self, tag: u8) -> Result<Expr, Error> {
+        Ok(match tag {
+            24 => Expr::Swap { a: self.str()?, b: self.str()? },
+            25 => Expr::Match { value: self.boxed()?, cases: self.pairs()? },
+            26 => Expr::Enum { name: self.str()?, fields: self.strs()? },
+            27 => Expr::EnumCall { name: self.str()?, field: self.str()? },
+            28 => Expr::To { value: self.boxed()?, to: self.type_()? },
+            29 => Expr::TryCatch { body: self.boxed()?, name: self.str()?, handler: self.boxed()? },
+            30 => Expr::Break,
+            31 => Expr::Continue,
+            _ => unreachable!(),
+        })
+    }
+
+    fn nodes_32_to_42(&mut self, tag: u8) -> Result<Expr, Error> {
+        Ok(match tag {
+            32 => Expr::Return { value: self.boxed()? },
+            33 => Expr::Spread { value: self.boxed()? },
+            34 => Expr::Empty,
+            35 => Expr::Map { entries: self.pairs()? },
+            36 => Expr::Tuple { elems: self.exprs()? },
+            37 => Expr::AssignMany { assigns: self.exprs()? },
+            38 => Expr::Lambda { args: self.exprs()?, body: self.boxed()? },
+            39 => Expr::Apply { func: self.boxed()?, args: self.exprs()? },
+            40 => Expr::SetIndex { name: self.str()?, index: self.boxed()?, value: self.boxed()? },
+            41 => Expr::With { name: self.str()?, value: self.boxed()?, body: self.boxed()? },
+            42 => Expr::SetAttr { name: self.str()?, attr: self.str()?, value: self.boxed()? },
+            _ => unreachable!(),
+        })
+    }
+}
@@ -1,14 +1,37 @@
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::Write;
 use std::rc::Rc;
 use crate::executer::Vm;
+use crate::executer::range_len;
 use crate::executer::value::Value;
 use crate::executer::value::Var;
+use crate::executer::value::Type;
+use crate::executer::value::Function;
+use crate::executer::value::MapKey;
 use crate::errors::Error;
+use crate::errors::TypeMismatchError;
+use crate::errors::InvalidArgumentError;
+use crate::errors::VarNotFoundError;
+use crate::errors::CannotMutateError;
+use crate::errors::CannotCompareError;
+use crate::errors::AssertionFailedError;
+use crate::executer::value::Ident;
+use crate::tree::Expr;
+use crate::tree::Literal;
 
 
 
+// Arguments passed beyond the declared parameters are keyed by their position.
+fn positional_args(args: &HashMap<String, Var>) -> Vec<Value> {
+    let mut positional = args
+        .iter()
+        .filter_map(|(k, v)| k.parse::<usize>().ok().map(|i| (i, v.value.clone())))
+        .collect::<Vec<(usize, Value)>>();
+    positional.sort_by_key(|(i, _)| *i);
+    positional.into_iter().map(|(_, v)| v).collect()
+}
+
 pub trait Builtin {
     type BuiltinValue;
     fn build() -> HashMap<String, (Self::BuiltinValue, Vec<String>)>;
@@ -20,42 +43,354 @@ impl Builtin for BuiltinFunction  {
     type BuiltinValue = Rc<dyn Fn(HashMap<String, Var>, Vm) -> Result<Value, Error>>;
     fn build() -> HashMap<String, (Self::BuiltinValue, Vec<String>)> {
         let mut map = HashMap::<String, (Self::BuiltinValue, Vec<String>)>::new();
-        map.insert("print".to_string(), (Rc::new(BuiltinFunction::print), vec!["msg".to_string()]));
-        map.insert("println".to_string(), (Rc::new(BuiltinFunction::println), vec!["msg".to_string()]));
+        map.insert("help".to_string(), (Rc::new(BuiltinFunction::help), vec![]));
+        map.insert("print".to_string(), (Rc::new(BuiltinFunction::print), vec![]));
+        map.insert("println".to_string(), (Rc::new(BuiltinFunction::println), vec![]));
+        map.insert("str".to_string(), (Rc::new(BuiltinFunction::str), vec!["value".to_string()]));
+        map.insert("int".to_string(), (Rc::new(BuiltinFunction::int), vec!["value".to_string()]));
+        map.insert("float".to_string(), (Rc::new(BuiltinFunction::float), vec!["value".to_string()]));
+        map.insert("type".to_string(), (Rc::new(BuiltinFunction::type_of), vec!["value".to_string()]));
+        map.insert("format".to_string(), (Rc::new(BuiltinFunction::format), vec!["template".to_string()]));
+        map.insert("debug".to_string(), (Rc::new(BuiltinFunction::debug), vec!["value".to_string()]));
+        map.insert("eprintln".to_string(), (Rc::new(BuiltinFunction::eprintln), vec![]));
         map.insert("len".to_string(), (Rc::new(BuiltinFunction::len), vec!["list".to_string()]));
+        map.insert("is_empty".to_string(), (Rc::new(BuiltinFunction::is_empty), vec!["value".to_string()]));
+        map.insert("non_empty".to_string(), (Rc::new(BuiltinFunction::non_empty), vec!["value".to_string()]));
+        map.insert("insert".to_string(), (Rc::new(BuiltinFunction::insert), vec!["map".to_string(), "key".to_string(), "value".to_string()]));
+        map.insert("push".to_string(), (Rc::new(BuiltinFunction::push), vec!["list_name".to_string(), "value".to_string()]));
+        map.insert("pop".to_string(), (Rc::new(BuiltinFunction::pop), vec!["list_name".to_string()]));
         map.insert("read".to_string(), (Rc::new(BuiltinFunction::read), vec!["msg".to_string()]));
+        map.insert("input".to_string(), (Rc::new(BuiltinFunction::input), vec!["prompt".to_string()]));
+        map.insert("repeat".to_string(), (Rc::new(BuiltinFunction::repeat), vec!["func".to_string(), "n".to_string()]));
+        map.insert("pad_left".to_string(), (Rc::new(BuiltinFunction::pad_left), vec!["s".to_string(), "width".to_string()]));
+        map.insert("pad_right".to_string(), (Rc::new(BuiltinFunction::pad_right), vec!["s".to_string(), "width".to_string()]));
+        map.insert("if_else".to_string(), (Rc::new(BuiltinFunction::if_else), vec!["cond".to_string(), "a".to_string(), "b".to_string()]));
+        map.insert("range".to_string(), (Rc::new(BuiltinFunction::range), vec!["start".to_string(), "end".to_string()]));
+        map.insert("map".to_string(), (Rc::new(BuiltinFunction::map), vec!["list".to_string(), "func".to_string()]));
+        map.insert("map_indexed".to_string(), (Rc::new(BuiltinFunction::map_indexed), vec!["list".to_string(), "func".to_string()]));
+        map.insert("flat_map".to_string(), (Rc::new(BuiltinFunction::flat_map), vec!["list".to_string(), "func".to_string()]));
+        map.insert("filter".to_string(), (Rc::new(BuiltinFunction::filter), vec!["list".to_string(), "pred".to_string()]));
+        map.insert("partition".to_string(), (Rc::new(BuiltinFunction::partition), vec!["list".to_string(), "pred".to_string()]));
+        map.insert("take_while".to_string(), (Rc::new(BuiltinFunction::take_while), vec!["list".to_string(), "pred".to_string()]));
+        map.insert("drop_while".to_string(), (Rc::new(BuiltinFunction::drop_while), vec!["list".to_string(), "pred".to_string()]));
+        map.insert("sort".to_string(), (Rc::new(BuiltinFunction::sort), vec!["list".to_string()]));
+        map.insert("contains".to_string(), (Rc::new(BuiltinFunction::contains), vec!["list".to_string(), "value".to_string()]));
+        map.insert("unique".to_string(), (Rc::new(BuiltinFunction::unique), vec!["list".to_string()]));
+        map.insert("zip".to_string(), (Rc::new(BuiltinFunction::zip), vec!["a".to_string(), "b".to_string()]));
+        map.insert("zip_longest".to_string(), (Rc::new(BuiltinFunction::zip_longest), vec!["a".to_string(), "b".to_string(), "fill".to_string()]));
+        map.insert("reduce".to_string(), (Rc::new(BuiltinFunction::reduce), vec!["list".to_string(), "func".to_string(), "init".to_string()]));
+        map.insert("pmap".to_string(), (Rc::new(BuiltinFunction::pmap), vec!["list".to_string(), "func".to_string()]));
+        map.insert("divmod".to_string(), (Rc::new(BuiltinFunction::divmod), vec!["a".to_string(), "b".to_string()]));
+        map.insert("abs".to_string(), (Rc::new(BuiltinFunction::abs), vec!["x".to_string()]));
+        map.insert("sqrt".to_string(), (Rc::new(BuiltinFunction::sqrt), vec!["x".to_string()]));
+        map.insert("floor".to_string(), (Rc::new(BuiltinFunction::floor), vec!["x".to_string()]));
+        map.insert("ceil".to_string(), (Rc::new(BuiltinFunction::ceil), vec!["x".to_string()]));
+        map.insert("round".to_string(), (Rc::new(BuiltinFunction::round), vec!["x".to_string()]));
+        map.insert("min".to_string(), (Rc::new(BuiltinFunction::min), vec![]));
+        map.insert("max".to_string(), (Rc::new(BuiltinFunction::max), vec![]));
+        map.insert("assert".to_string(), (Rc::new(BuiltinFunction::assert), vec!["cond".to_string()]));
+        map.insert("assert_type".to_string(), (Rc::new(BuiltinFunction::assert_type), vec!["value".to_string(), "type".to_string()]));
+        map.insert("assert_raises".to_string(), (Rc::new(BuiltinFunction::assert_raises), vec!["func".to_string()]));
+        #[cfg(feature = "regex")]
+        map.insert("matches".to_string(), (Rc::new(BuiltinFunction::matches), vec!["s".to_string(), "pattern".to_string()]));
+        #[cfg(feature = "regex")]
+        map.insert("find_all".to_string(), (Rc::new(BuiltinFunction::find_all), vec!["s".to_string(), "pattern".to_string()]));
         map
     }
     
 }
 
+// One line per builtin for `help`.
+fn description(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "help" => "lists the builtins, or describes the one named",
+        "print" => "writes its arguments joined by the separator",
+        "println" => "writes its arguments joined by the separator, then a newline",
+        "eprintln" => "writes its arguments and a newline to the error output",
+        "debug" => "writes the debug form of a value and returns it",
+        "str" => "renders a value as print would",
+        "int" => "parses a string or truncates a float into an int",
+        "float" => "parses a string or converts an int into a float",
+        "type" => "names the type of a value",
+        "format" => "fills each {} in a template with the next argument",
+        "len" => "counts the elements of a list, string, range or map",
+        "is_empty" => "tells whether a value has no elements",
+        "non_empty" => "tells whether a value has elements",
+        "insert" => "adds a key and value to the map variable named",
+        "push" => "appends a value to the list variable named",
+        "pop" => "removes and returns the last element of the list variable named",
+        "read" => "writes a prompt and returns the next input line",
+        "input" => "writes a prompt and returns the next input line, or None at the end",
+        "repeat" => "calls a function n times",
+        "pad_left" => "pads a string on the left to a width",
+        "pad_right" => "pads a string on the right to a width",
+        "if_else" => "picks a or b depending on a condition",
+        "range" => "lists the ints from start to end, with an optional step",
+        "map" => "applies a function to each element of a list",
+        "map_indexed" => "applies a function to each index and element of a list",
+        "pmap" => "applies a pure function to each element of a list on several threads",
+        "flat_map" => "applies a list-returning function to each element of a list and concatenates the results",
+        "filter" => "keeps the elements of a list a predicate holds for",
+        "partition" => "splits a list into the elements a predicate holds for and the rest",
+        "take_while" => "the leading elements of a list a predicate holds for",
+        "drop_while" => "a list without the leading elements a predicate holds for",
+        "reduce" => "folds a list from the left, starting from init",
+        "sort" => "sorts a list, optionally with a function telling which comes first",
+        "contains" => "whether a list has an element equal to a value",
+        "unique" => "a list without repeated elements, keeping the first of each",
+        "zip" => "pairs up the elements of two lists, stopping at the shorter",
+        "zip_longest" => "pairs up the elements of two lists, padding the shorter with a fill value",
+        "divmod" => "returns the quotient and remainder as a tuple",
+        "abs" => "the absolute value of a number",
+        "sqrt" => "the square root of a non-negative number",
+        "floor" => "rounds a number down",
+        "ceil" => "rounds a number up",
+        "round" => "rounds a number to the nearest whole one, halves away from zero or with 'half_even' to the even one",
+        "min" => "the smallest of its arguments or of a list",
+        "max" => "the largest of its arguments or of a list",
+        "assert" => "fails, with an optional message, unless a condition is truthy",
+        "assert_type" => "fails unless a value has the type named",
+        "assert_raises" => "calls a function, failing unless it raises, and returns the error",
+        "matches" => "tells whether a string matches a regex",
+        "find_all" => "lists the matches of a regex in a string",
+        _ => return None,
+    })
+}
+
 impl BuiltinFunction {
-    pub fn print(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
-        for i in args {
-            print!("{}", i.1.value.display_value());
+    pub fn help(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match positional_args(&args).first() {
+            Some(Value::String(name)) => Ok(description(name)
+                .filter(|_| BuiltinFunction::build().contains_key(name))
+                .map(|d| Value::String(d.to_string()))
+                .unwrap_or(Value::None)),
+            Some(value) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::String,
+                found: value.get_type(),
+            })),
+            None => {
+                let mut names = BuiltinFunction::build().into_keys().collect::<Vec<String>>();
+                names.sort();
+                Ok(Value::List(names.into_iter().map(Value::String).collect()))
+            }
         }
+    }
+
+    pub fn print(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let msg = positional_args(&args)
+            .iter()
+            .map(|v| v.display_with(vm.display_format()))
+            .collect::<Vec<String>>()
+            .join(vm.separator());
+        vm.write_output(&msg);
         Ok(Value::None)
     }
 
     pub fn println(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
-        for i in args {
-            print!("{}", i.1.value.display_value());
-        }
-        println!();
+        BuiltinFunction::print(args, vm.clone())?;
+        vm.write_output("\n");
         Ok(Value::None)
     }
 
-    pub fn len(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
-        if args.len() != 1 {
-            return Ok(Value::None);
-        } else {
-            let value = args.get("0").unwrap();
-            Ok(match value {
-                Var {value: Value::String(s), ..} => Value::Number(s.len() as f64),
-                Var {value: Value::List(l), ..} => Value::Number(l.len() as f64),
-                _ => Value::None,
-            })
+    pub fn eprintln(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let msg = positional_args(&args)
+            .iter()
+            .map(|v| v.display_with(vm.display_format()))
+            .collect::<Vec<String>>()
+            .join(vm.separator());
+        vm.write_error(&msg);
+        vm.write_error("\n");
+        Ok(Value::None)
+    }
+
+    // Each `{}` in the template takes the next argument, rendered as `print`
+    // would; `{{` and `}}` stand for literal braces.
+    pub fn format(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let template = match args.get("template") {
+            Some(Var {value: Value::String(s), ..}) => s.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::String,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        let invalid = |reason: String| Error::InvalidArgument(InvalidArgumentError {
+            name: "format".to_string(),
+            reason,
+        });
+        let values = positional_args(&args);
+        let mut values = values.iter();
+        let mut out = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    out.push(c);
+                }
+                ('{', Some('}')) => {
+                    chars.next();
+                    let value = values.next().ok_or_else(|| invalid("more placeholders than arguments".to_string()))?;
+                    out.push_str(&value.display_with(vm.display_format()));
+                }
+                ('{' | '}', _) => return Err(invalid(format!("unmatched {} in template", c))),
+                _ => out.push(c),
+            }
         }
+        if values.next().is_some() {
+            return Err(invalid("more arguments than placeholders".to_string()));
+        }
+        Ok(Value::String(out))
+    }
+
+    pub fn str(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        Ok(match args.get("value") {
+            Some(var) => Value::String(var.value.display_value()),
+            None => Value::None,
+        })
+    }
+
+    pub fn int(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("value") {
+            Some(var) => var.value.cast(&Type::Int),
+            None => Ok(Value::None),
+        }
+    }
+
+    pub fn float(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("value") {
+            Some(var) => var.value.cast(&Type::Float),
+            None => Ok(Value::None),
+        }
+    }
+
+    pub fn type_of(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        Ok(match args.get("value") {
+            Some(var) => Value::String(var.value.get_type().variant_name()),
+            None => Value::None,
+        })
+    }
+
+    pub fn debug(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let value = match args.get("value") {
+            Some(var) => var.value.clone(),
+            None => Value::None,
+        };
+        vm.write_output(&value.debug_repr());
+        vm.write_output("\n");
+        Ok(value)
+    }
+
+    pub fn len(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("list") {
+            Some(Var {value: Value::String(s), ..}) => Ok(Value::Int(s.chars().count() as i64)),
+            Some(Var {value: Value::List(l), ..}) => Ok(Value::Int(l.len() as i64)),
+            Some(Var {value: Value::Range(r), ..}) => Ok(Value::Int(range_len(r) as i64)),
+            Some(Var {value: Value::Map(m), ..}) => Ok(Value::Int(m.len() as i64)),
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: var.value.get_type(),
+            })),
+            None => Ok(Value::None),
+        }
+    }
+
+    pub fn is_empty(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("value") {
+            Some(var) => Ok(Value::Bool(var.value.is_empty()?)),
+            None => Ok(Value::None),
+        }
+    }
+
+    pub fn non_empty(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("value") {
+            Some(var) => Ok(Value::Bool(!var.value.is_empty()?)),
+            None => Ok(Value::None),
+        }
+    }
+
+    // Returns a copy of the map with the entry added or replaced.
+    pub fn insert(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let mut map = match args.get("map") {
+            Some(Var {value: Value::Map(m), ..}) => m.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Map,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        let key = match args.get("key") {
+            Some(var) => MapKey::from_value(&var.value)?,
+            None => return Ok(Value::None),
+        };
+        let value = match args.get("value") {
+            Some(var) => var.value.clone(),
+            None => Value::None,
+        };
+        if !map.contains_key(&key) {
+            vm.check_length(map.len() + 1)?;
+        }
+        map.insert(key, value);
+        Ok(Value::Map(map))
+    }
+
+    // Lists are stored by value, so push and pop take the name of a mutable
+    // list variable and write the updated list back into its scope.
+    fn list_var(args: &HashMap<String, Var>, vm: &Vm) -> Result<(String, Vec<Value>), Error> {
+        let name = match args.get("list_name") {
+            Some(Var {value: Value::String(s), ..}) => s.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::String,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => String::new(),
+        };
+        match vm.get_ident(&name) {
+            Some(Var {mutable: false, ..}) => Err(Error::CannotMutate(CannotMutateError {
+                var_name: name,
+            })),
+            Some(Var {value: Value::List(l), ..}) => Ok((name, l)),
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: var.value.get_type(),
+            })),
+            None => Err(Error::VarNotFound(VarNotFoundError {
+                var_name: name,
+            })),
+        }
+    }
+
+    pub fn push(args: HashMap<String, Var>, mut vm: Vm) -> Result<Value, Error> {
+        let (name, mut list) = BuiltinFunction::list_var(&args, &vm)?;
+        let value = match args.get("value") {
+            Some(var) => var.value.clone(),
+            None => Value::None,
+        };
+        vm.check_length(list.len() + 1)?;
+        list.push(value);
+        vm.update_ident(Ident(name), Var {value: Value::List(list), type_: Type::List, mutable: true});
+        Ok(Value::None)
+    }
+
+    pub fn pop(args: HashMap<String, Var>, mut vm: Vm) -> Result<Value, Error> {
+        let (name, mut list) = BuiltinFunction::list_var(&args, &vm)?;
+        let last = match list.pop() {
+            Some(last) => last,
+            None => {
+                return Err(Error::InvalidArgument(InvalidArgumentError {
+                    name: "pop".to_string(),
+                    reason: format!("list {} is empty", name),
+                }))
+            }
+        };
+        vm.update_ident(Ident(name), Var {value: Value::List(list), type_: Type::List, mutable: true});
+        Ok(last)
     }
 
     pub fn read(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
@@ -65,17 +400,677 @@ impl BuiltinFunction {
             let value = args.get("msg").unwrap();
             Ok(match value {
                 Var {value: Value::String(s), ..} => {
-                    let mut input = String::new();
-                    print!("{}", s);
-                    std::io::stdout().flush();
-                    std::io::stdin().read_line(&mut input).expect("Failed to read line");
-                    if input.ends_with("\n") {
-                        input.pop();
-                    }
-                    Value::String(input.to_string())
+                    vm.write_output(s);
+                    Value::String(vm.read_line().unwrap_or_default())
                 },
                 _ => Value::None,
             })
         }
     }
-}
\ No newline at end of file
+
+    // Unlike `read`, any value can be the prompt, and the end of the input
+    // gives `None` rather than an empty string.
+    pub fn input(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        if let Some(var) = args.get("prompt") {
+            vm.write_output(&var.value.display_with(vm.display_format()));
+        }
+        Ok(vm.read_line().map(Value::String).unwrap_or(Value::None))
+    }
+
+    pub fn repeat(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let func = match args.get("func") {
+            Some(Var {value: Value::Function { func, .. }, ..}) => func.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Func,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        let n = match args.get("n") {
+            Some(var) => var.value.as_index("repeat", "count")?,
+            None => return Ok(Value::None),
+        };
+
+        let Function(f, _) = func;
+        let mut last = Value::None;
+        for _ in 0..n {
+            last = f(HashMap::new(), vm.clone())?;
+        }
+        Ok(last)
+    }
+
+    pub fn assert_type(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let value = match args.get("value") {
+            Some(var) => var.value.clone(),
+            None => Value::None,
+        };
+        let name = match args.get("type") {
+            Some(Var {value: Value::String(s), ..}) => s.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::String,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        if value.get_type().name() != name {
+            return Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::from_name(&name),
+                found: value.get_type(),
+            }));
+        }
+        Ok(value)
+    }
+
+    // An optional second argument replaces the message of the failure.
+    pub fn assert(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let cond = match args.get("cond") {
+            Some(var) => &var.value,
+            None => return Ok(Value::None),
+        };
+        if cond.is_truthy() {
+            return Ok(Value::None);
+        }
+        let message = match args.get("1") {
+            Some(var) => var.value.display_value(),
+            None => format!("{} is not truthy", cond.debug_repr()),
+        };
+        Err(Error::AssertionFailed(AssertionFailedError { message }))
+    }
+
+    // Calls a function without arguments and returns the error it raises,
+    // as `try`/`catch` would bind it.
+    pub fn assert_raises(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let func = match args.get("func") {
+            Some(var) => var.value.clone(),
+            None => return Ok(Value::None),
+        };
+        match vm.call_value(&func, vec![]) {
+            Ok(value) => Err(Error::AssertionFailed(AssertionFailedError {
+                message: format!("expected an error, got {}", value.debug_repr()),
+            })),
+            Err(err) if err.is_control_flow() => Err(err),
+            Err(err) => Ok(err.to_value()),
+        }
+    }
+
+    // An optional third argument gives the fill character, which defaults to a space.
+    fn pad_args(name: &str, args: &HashMap<String, Var>) -> Result<(String, String), Error> {
+        let s = match args.get("s") {
+            Some(Var {value: Value::String(s), ..}) => s.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::String,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => String::new(),
+        };
+        let width = match args.get("width") {
+            Some(var) => var.value.as_index(name, "width")?,
+            None => 0,
+        };
+        let fill = match args.get("2") {
+            Some(Var {value: Value::String(f), ..}) if f.chars().count() == 1 => f.clone(),
+            Some(var) => {
+                return Err(Error::InvalidArgument(InvalidArgumentError {
+                    name: name.to_string(),
+                    reason: format!("fill must be a single character, found {}", var.value),
+                }))
+            }
+            None => " ".to_string(),
+        };
+        let padding = fill.repeat(width.saturating_sub(s.chars().count()));
+        Ok((s, padding))
+    }
+
+    pub fn pad_left(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (s, padding) = BuiltinFunction::pad_args("pad_left", &args)?;
+        Ok(Value::String(padding + &s))
+    }
+
+    pub fn pad_right(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (s, padding) = BuiltinFunction::pad_args("pad_right", &args)?;
+        Ok(Value::String(s + &padding))
+    }
+
+    // Unlike an `if` expression, both branches are evaluated before the call,
+    // and the condition must be a bool just like with `to bool`.
+    pub fn if_else(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let branch = match args.get("cond") {
+            Some(Var {value: Value::Bool(true), ..}) => "a",
+            Some(Var {value: Value::Bool(false), ..}) => "b",
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Bool,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        Ok(args.get(branch).map(|var| var.value.clone()).unwrap_or(Value::None))
+    }
+
+    // An optional third argument gives the step, which may be negative.
+    pub fn range(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let mut bounds = Vec::new();
+        for key in ["start", "end", "2"] {
+            match args.get(key) {
+                Some(var) => bounds.push(var.value.as_int()?),
+                None if key == "2" => bounds.push(1),
+                None => return Ok(Value::None),
+            }
+        }
+        let (start, end, step) = (bounds[0], bounds[1], bounds[2]);
+        if step == 0 {
+            return Err(Error::InvalidArgument(InvalidArgumentError {
+                name: "range".to_string(),
+                reason: "step must not be zero".to_string(),
+            }));
+        }
+        let span = if step > 0 { end.saturating_sub(start) } else { start.saturating_sub(end) };
+        let count = if span > 0 { (span as u64 - 1) / step.unsigned_abs() + 1 } else { 0 };
+        vm.check_length(count as usize)?;
+        Ok(Value::List((0..count as i64).map(|i| Value::Int(start + i * step)).collect()))
+    }
+
+    fn map_args(name: &str, func: &str, args: &HashMap<String, Var>) -> Result<(Vec<Value>, Value), Error> {
+        let list = match args.get("list") {
+            Some(Var {value: Value::List(l), ..}) => l.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::List,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => Vec::new(),
+        };
+        match args.get(func) {
+            Some(Var {value: f @ Value::Function { .. }, ..}) => Ok((list, f.clone())),
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Func,
+                found: var.value.get_type(),
+            })),
+            None => Err(Error::InvalidArgument(InvalidArgumentError {
+                name: name.to_string(),
+                reason: format!("missing argument {}", func),
+            })),
+        }
+    }
+
+    pub fn map(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, func) = BuiltinFunction::map_args("map", "func", &args)?;
+        let mut mapped = Vec::with_capacity(list.len());
+        for item in list {
+            mapped.push(vm.call_value(&func, vec![item])?);
+        }
+        Ok(Value::List(mapped))
+    }
+
+    pub fn map_indexed(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, func) = BuiltinFunction::map_args("map_indexed", "func", &args)?;
+        let mut mapped = Vec::with_capacity(list.len());
+        for (i, item) in list.into_iter().enumerate() {
+            mapped.push(vm.call_value(&func, vec![Value::Int(i as i64), item])?);
+        }
+        Ok(Value::List(mapped))
+    }
+
+    // Every result must be a list; anything else is a type mismatch rather than a singleton.
+    pub fn flat_map(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, func) = BuiltinFunction::map_args("flat_map", "func", &args)?;
+        let mut flat = Vec::new();
+        for item in list {
+            match vm.call_value(&func, vec![item])? {
+                Value::List(items) => flat.extend(items),
+                other => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::List,
+                        found: other.get_type(),
+                    }))
+                }
+            }
+        }
+        Ok(Value::List(flat))
+    }
+
+    pub fn filter(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, pred) = BuiltinFunction::map_args("filter", "pred", &args)?;
+        let mut kept = Vec::new();
+        for item in list {
+            if vm.call_value(&pred, vec![item.clone()])?.is_truthy() {
+                kept.push(item);
+            }
+        }
+        Ok(Value::List(kept))
+    }
+
+    pub fn partition(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, pred) = BuiltinFunction::map_args("partition", "pred", &args)?;
+        let (mut matches, mut rest) = (Vec::new(), Vec::new());
+        for item in list {
+            if vm.call_value(&pred, vec![item.clone()])?.is_truthy() {
+                matches.push(item);
+            } else {
+                rest.push(item);
+            }
+        }
+        Ok(Value::Tuple(vec![Value::List(matches), Value::List(rest)]))
+    }
+
+    // How many leading elements `pred` holds for; it is not called past the first miss.
+    fn leading(list: &[Value], pred: &Value, vm: &Vm) -> Result<usize, Error> {
+        for (i, item) in list.iter().enumerate() {
+            if !vm.call_value(pred, vec![item.clone()])?.is_truthy() {
+                return Ok(i);
+            }
+        }
+        Ok(list.len())
+    }
+
+    pub fn take_while(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (mut list, pred) = BuiltinFunction::map_args("take_while", "pred", &args)?;
+        list.truncate(BuiltinFunction::leading(&list, &pred, &vm)?);
+        Ok(Value::List(list))
+    }
+
+    pub fn drop_while(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (mut list, pred) = BuiltinFunction::map_args("drop_while", "pred", &args)?;
+        let n = BuiltinFunction::leading(&list, &pred, &vm)?;
+        Ok(Value::List(list.split_off(n)))
+    }
+
+    pub fn reduce(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, func) = BuiltinFunction::map_args("reduce", "func", &args)?;
+        let mut acc = args.get("init").map(|var| var.value.clone()).unwrap_or(Value::None);
+        for item in list {
+            acc = vm.call_value(&func, vec![acc, item])?;
+        }
+        Ok(acc)
+    }
+
+    // An optional second argument is a function telling whether its first
+    // argument sorts before its second; equal elements keep their order.
+    pub fn sort(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let list = match args.get("list") {
+            Some(Var {value: Value::List(l), ..}) => l.clone(),
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::List,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => return Ok(Value::None),
+        };
+        let sorted = match args.get("1") {
+            Some(Var {value: f @ Value::Function { .. }, ..}) => merge_sort(list, &mut |a, b| {
+                Ok(vm.call_value(f, vec![a.clone(), b.clone()])?.is_truthy())
+            })?,
+            Some(var) => {
+                return Err(Error::TypeMismatch(TypeMismatchError {
+                    expected: Type::Func,
+                    found: var.value.get_type(),
+                }))
+            }
+            None => merge_sort(list, &mut |a, b| match a.compare(b)? {
+                Some(ordering) => Ok(ordering == Ordering::Less),
+                None => Err(Error::CannotCompare(CannotCompareError {
+                    left: a.to_string(),
+                    right: b.to_string(),
+                })),
+            })?,
+        };
+        Ok(Value::List(sorted))
+    }
+
+    // Values `==` cannot compare, such as an int and a string, count as different.
+    fn same(a: &Value, b: &Value) -> bool {
+        matches!(a.eq(b), Ok(Value::Bool(true)))
+    }
+
+    pub fn contains(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match (args.get("list"), args.get("value")) {
+            (Some(Var {value: Value::List(l), ..}), Some(var)) => {
+                Ok(Value::Bool(l.iter().any(|item| BuiltinFunction::same(item, &var.value))))
+            }
+            (Some(Var {value: Value::List(_), ..}), None) | (None, _) => Ok(Value::None),
+            (Some(var), _) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: var.value.get_type(),
+            })),
+        }
+    }
+
+    fn zip_args(args: &HashMap<String, Var>) -> Result<(Vec<Value>, Vec<Value>), Error> {
+        let list = |key: &str| match args.get(key) {
+            Some(Var {value: Value::List(l), ..}) => Ok(l.clone()),
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: var.value.get_type(),
+            })),
+            None => Ok(Vec::new()),
+        };
+        Ok((list("a")?, list("b")?))
+    }
+
+    pub fn zip(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (a, b) = BuiltinFunction::zip_args(&args)?;
+        Ok(Value::List(a.into_iter().zip(b).map(|(x, y)| Value::Tuple(vec![x, y])).collect()))
+    }
+
+    pub fn zip_longest(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (a, b) = BuiltinFunction::zip_args(&args)?;
+        let fill = args.get("fill").map(|var| var.value.clone()).unwrap_or(Value::None);
+        let length = a.len().max(b.len());
+        let (mut a, mut b) = (a.into_iter(), b.into_iter());
+        Ok(Value::List((0..length).map(|_| {
+            let x = a.next().unwrap_or_else(|| fill.clone());
+            let y = b.next().unwrap_or_else(|| fill.clone());
+            Value::Tuple(vec![x, y])
+        }).collect()))
+    }
+
+    pub fn unique(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match args.get("list") {
+            Some(Var {value: Value::List(l), ..}) => {
+                let mut kept: Vec<Value> = Vec::new();
+                for item in l {
+                    if !kept.iter().any(|seen| BuiltinFunction::same(seen, item)) {
+                        kept.push(item.clone());
+                    }
+                }
+                Ok(Value::List(kept))
+            }
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::List,
+                found: var.value.get_type(),
+            })),
+            None => Ok(Value::None),
+        }
+    }
+
+    // `Vm` is not `Send`, so each worker thread rebuilds the function from its
+    // `def` body in a fresh interpreter, and elements and results cross over
+    // as the literal expressions that rebuild them. The function must be pure:
+    // a worker only sees the function itself and the builtins, so a function
+    // that uses input or output, or a builtin the caller has redefined, is
+    // mapped on the calling thread instead. Workers share the caller's
+    // operators, display format and limits, including the remaining step
+    // budget, and the steps they take are charged to the caller. Elements a
+    // worker cannot handle, including any that fail, are mapped again on the
+    // calling thread, so errors are reported as `map` would report them.
+    pub fn pmap(args: HashMap<String, Var>, vm: Vm) -> Result<Value, Error> {
+        let (list, func) = BuiltinFunction::map_args("pmap", "func", &args)?;
+        let def = match &func {
+            Value::Function { name, func: Function(_, Some(body)), args: params } => Expr::FunDef {
+                name: name.clone(),
+                args: params.iter().map(|p| Expr::Ident { ident: p.clone() }).collect(),
+                body: Box::new((**body).clone()),
+            },
+            _ => return BuiltinFunction::map(args, vm),
+        };
+        if !vm.runs_like_a_worker(&def, &IO_BUILTINS) {
+            return BuiltinFunction::map(args, vm);
+        }
+
+        let items = list.iter().map(to_literal).collect::<Vec<Option<Expr>>>();
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = items.len().div_ceil(threads).max(1);
+        let config = vm.worker_config();
+        let (results, steps) = std::thread::scope(|scope| {
+            let workers = items
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let (def, config) = (&def, &config);
+                    (chunk.len(), scope.spawn(move || {
+                        let mut worker = Vm::with_worker_config(config);
+                        let f = worker.eval_expr(def.clone()).ok();
+                        let results = chunk
+                            .iter()
+                            .map(|item| {
+                                let value = worker.eval_expr(item.clone()?).ok()?;
+                                to_literal(&worker.call_value(f.as_ref()?, vec![value]).ok()?)
+                            })
+                            .collect::<Vec<Option<Expr>>>();
+                        (results, worker.steps_taken())
+                    }))
+                })
+                .collect::<Vec<_>>();
+            let mut steps = 0;
+            let mut results = Vec::with_capacity(items.len());
+            for (len, worker) in workers {
+                match worker.join() {
+                    Ok((chunk, taken)) => {
+                        results.extend(chunk);
+                        steps += taken;
+                    }
+                    Err(_) => results.extend(vec![None; len]),
+                }
+            }
+            (results, steps)
+        });
+        vm.charge_steps(steps)?;
+
+        let mut mapped = Vec::with_capacity(list.len());
+        for (item, result) in list.into_iter().zip(results) {
+            mapped.push(match result {
+                Some(literal) => vm.clone().eval_expr(literal)?,
+                None => vm.call_value(&func, vec![item])?,
+            });
+        }
+        Ok(Value::List(mapped))
+    }
+
+    pub fn divmod(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (a, b) = match (args.get("a"), args.get("b")) {
+            (Some(a), Some(b)) => (&a.value, &b.value),
+            _ => return Ok(Value::None),
+        };
+        let quotient = match a.div(b)? {
+            Value::Float(q) => Value::Float(q.trunc()),
+            q => q,
+        };
+        Ok(Value::Tuple(vec![quotient, a.modulo(b)?]))
+    }
+
+    fn number_arg(args: &HashMap<String, Var>) -> Result<Option<Value>, Error> {
+        match args.get("x") {
+            Some(Var {value: n @ (Value::Int(_) | Value::Float(_)), ..}) => Ok(Some(n.clone())),
+            Some(var) => Err(Error::TypeMismatch(TypeMismatchError {
+                expected: Type::Float,
+                found: var.value.get_type(),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    // Ints are already whole, so rounding leaves them as they are.
+    fn round_with(args: &HashMap<String, Var>, round: fn(f64) -> f64) -> Result<Value, Error> {
+        Ok(match BuiltinFunction::number_arg(args)? {
+            Some(Value::Float(n)) => Value::Float(round(n)),
+            Some(n) => n,
+            None => Value::None,
+        })
+    }
+
+    pub fn abs(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        match BuiltinFunction::number_arg(&args)? {
+            Some(Value::Int(n)) => n.checked_abs().map(Value::Int).ok_or_else(|| {
+                Error::InvalidArgument(InvalidArgumentError {
+                    name: "abs".to_string(),
+                    reason: format!("{} has no positive counterpart", n),
+                })
+            }),
+            Some(Value::Float(n)) => Ok(Value::Float(n.abs())),
+            _ => Ok(Value::None),
+        }
+    }
+
+    pub fn sqrt(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let n = match BuiltinFunction::number_arg(&args)? {
+            Some(Value::Int(n)) => n as f64,
+            Some(Value::Float(n)) => n,
+            _ => return Ok(Value::None),
+        };
+        if n < 0.0 {
+            return Err(Error::InvalidArgument(InvalidArgumentError {
+                name: "sqrt".to_string(),
+                reason: format!("cannot take the square root of negative {}", n),
+            }));
+        }
+        Ok(Value::Float(n.sqrt()))
+    }
+
+    pub fn floor(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        BuiltinFunction::round_with(&args, f64::floor)
+    }
+
+    pub fn ceil(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        BuiltinFunction::round_with(&args, f64::ceil)
+    }
+
+    pub fn round(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let round = match args.get("1").map(|var| &var.value) {
+            None => f64::round,
+            Some(Value::String(mode)) if mode == "half_up" => f64::round,
+            Some(Value::String(mode)) if mode == "half_even" => f64::round_ties_even,
+            Some(mode) => {
+                return Err(Error::InvalidArgument(InvalidArgumentError {
+                    name: "round".to_string(),
+                    reason: format!("unknown rounding mode {}, expected 'half_up' or 'half_even'", mode),
+                }))
+            }
+        };
+        BuiltinFunction::round_with(&args, round)
+    }
+
+    // Takes either several arguments or a single list of them. On a tie the
+    // first wins.
+    fn pick(name: &str, args: &HashMap<String, Var>, wanted: Ordering) -> Result<Value, Error> {
+        let values = match positional_args(args).as_slice() {
+            [Value::List(list)] => list.clone(),
+            values => values.to_vec(),
+        };
+        let mut values = values.into_iter();
+        let mut best = values.next().ok_or_else(|| {
+            Error::InvalidArgument(InvalidArgumentError {
+                name: name.to_string(),
+                reason: "no values to compare".to_string(),
+            })
+        })?;
+        for value in values {
+            match value.compare(&best)? {
+                Some(ordering) if ordering == wanted => best = value,
+                Some(_) => {}
+                None => {
+                    return Err(Error::CannotCompare(CannotCompareError {
+                        left: best.to_string(),
+                        right: value.to_string(),
+                    }))
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    pub fn min(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        BuiltinFunction::pick("min", &args, Ordering::Less)
+    }
+
+    pub fn max(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        BuiltinFunction::pick("max", &args, Ordering::Greater)
+    }
+
+    #[cfg(feature = "regex")]
+    fn regex_args(name: &str, args: &HashMap<String, Var>) -> Result<(String, regex::Regex), Error> {
+        let mut strings = Vec::new();
+        for key in ["s", "pattern"] {
+            match args.get(key) {
+                Some(Var {value: Value::String(s), ..}) => strings.push(s.clone()),
+                Some(var) => {
+                    return Err(Error::TypeMismatch(TypeMismatchError {
+                        expected: Type::String,
+                        found: var.value.get_type(),
+                    }))
+                }
+                None => {
+                    return Err(Error::InvalidArgument(InvalidArgumentError {
+                        name: name.to_string(),
+                        reason: format!("missing argument {}", key),
+                    }))
+                }
+            }
+        }
+        let regex = regex::Regex::new(&strings[1]).map_err(|e| Error::InvalidArgument(InvalidArgumentError {
+            name: name.to_string(),
+            reason: e.to_string(),
+        }))?;
+        Ok((strings.swap_remove(0), regex))
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn matches(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (s, regex) = BuiltinFunction::regex_args("matches", &args)?;
+        Ok(Value::Bool(regex.is_match(&s)))
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn find_all(args: HashMap<String, Var>, _vm: Vm) -> Result<Value, Error> {
+        let (s, regex) = BuiltinFunction::regex_args("find_all", &args)?;
+        Ok(Value::List(
+            regex.find_iter(&s).map(|m| Value::String(m.as_str().to_string())).collect()
+        ))
+    }
+}
+
+// Builtins that read from or write to the interpreter's streams, which a
+// `pmap` worker does not have.
+const IO_BUILTINS: [&str; 6] = ["print", "println", "eprintln", "debug", "read", "input"];
+
+// The literal that evaluates back to `value`, for values made of plain data.
+fn to_literal(value: &Value) -> Option<Expr> {
+    Some(match value {
+        Value::Int(n) => Expr::Literal { value: Literal::Int(*n) },
+        Value::Float(n) => Expr::Literal { value: Literal::Float(*n) },
+        Value::String(s) => Expr::Literal { value: Literal::String(s.clone()) },
+        Value::Bool(b) => Expr::Literal { value: Literal::Bool(*b) },
+        Value::List(items) => Expr::List { elems: items.iter().map(to_literal).collect::<Option<Vec<Expr>>>()? },
+        Value::Tuple(items) => Expr::Tuple { elems: items.iter().map(to_literal).collect::<Option<Vec<Expr>>>()? },
+        Value::Map(map) => Expr::Map {
+            entries: map
+                .iter()
+                .map(|(k, v)| Some((to_literal(&k.to_value())?, to_literal(v)?)))
+                .collect::<Option<Vec<(Expr, Expr)>>>()?,
+        },
+        Value::None => Expr::Empty,
+        _ => return None,
+    })
+}
+
+// A comparison can fail, which rules out `slice::sort_by`.
+fn merge_sort(
+    mut items: Vec<Value>,
+    before: &mut dyn FnMut(&Value, &Value) -> Result<bool, Error>,
+) -> Result<Vec<Value>, Error> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, before)?;
+    let right = merge_sort(right, before)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if before(r, l)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
@@ -53,6 +53,22 @@ fn assert_expr_eq(string: &str, expr_tested: Expr) {
     )
 }
 
+#[cfg(test)]
+fn eval_source(vm: &mut Vm, string: &str) -> Result<value::Value, crate::errors::Error> {
+    let exprs = tlang::ExprsParser::new()
+        .parse(string)
+        .expect("Something went wrong parsing the source");
+    vm.eval_expr(exprs)
+}
+
+#[cfg(test)]
+fn define_host_function<F>(vm: &mut Vm, name: &str, args: Vec<&str>, f: F)
+where
+    F: Fn(std::collections::HashMap<String, value::Var>, Vm) -> Result<value::Value, crate::errors::Error> + 'static,
+{
+    vm.define_function(name, &args, f);
+}
+
 #[test]
 fn test_def_var_syntax() {
     assert!(matches!(tlang::ExprsParser::new().parse("let = 5"), Err(_)))
@@ -67,10 +83,10 @@ fn test_def_var_expr() {
                 Expr::Assign { 
                     name: "a".to_string(),
                     value: Box::new(
-                        Expr::Literal { value: Literal::Number(5.0) } 
+                        Expr::Literal { value: Literal::Int(5) } 
                     ),
-                    mutable: true,
-                    type_: Some(Type::Int)
+                    mutable: false,
+                    type_: None
                 }
             ] 
         }
@@ -88,8 +104,8 @@ fn test_op_expr() {
         vec![
             Expr::BinOp { 
                 op: Op::Add, 
-                left: Box::new(Expr::Literal {value: Literal::Number(5.)}), 
-                right: Box::new(Expr::Literal {value: Literal::Number(8.)})
+                left: Box::new(Expr::Literal {value: Literal::Int(5)}), 
+                right: Box::new(Expr::Literal {value: Literal::Int(8)})
             }
         ]
     })
@@ -107,8 +123,8 @@ fn test_cmp_op_expr() {
         vec![
             Expr::BinOp { 
                 op: Op::Lt, 
-                left: Box::new(Expr::Literal {value: Literal::Number(5.)}), 
-                right: Box::new(Expr::Literal {value: Literal::Number(8.)})
+                left: Box::new(Expr::Literal {value: Literal::Int(5)}), 
+                right: Box::new(Expr::Literal {value: Literal::Int(8)})
             }
         ]
     })
@@ -126,7 +142,7 @@ fn test_iop_expr() {
             Expr::IOp { 
                 op: IOp::IAdd, 
                 value: Box::new(
-                    Expr::Literal { value: Literal::Number(8.) }
+                    Expr::Literal { value: Literal::Int(8) }
                 ),
                 name: "a".to_string() }
         ]
@@ -204,15 +220,2409 @@ fn test_func_expr() {
 
 #[test] 
 fn test_def_var_value() {
-    test_value("/Users/antoine/Documents/tlang/src/tlang_asset/test_def_var.txt", value::Value::Number(5.))
+    test_value(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tlang_asset/test_def_var.txt"), value::Value::Int(5))
 }
 
 #[test] 
 fn test_op_value() {
-    test_value("/Users/antoine/Documents/tlang/src/tlang_asset/test_op.txt", value::Value::Number(8.))
+    test_value(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tlang_asset/test_op.txt"), value::Value::Int(8))
 }
 
 #[test]
 fn test_cmp_op_value() {
-    test_value("/Users/antoine/Documents/tlang/src/tlang_asset/test_cmp_op.txt", value::Value::Bool(true))
+    test_value(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tlang_asset/test_cmp_op.txt"), value::Value::Bool(true))
+}
+
+#[test]
+fn test_repeat_value() {
+    let count = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = count.clone();
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "tick", vec![], move |_, _| {
+        counter.set(counter.get() + 1);
+        Ok(value::Value::Int(counter.get() as i64))
+    });
+    assert_eq!(eval_source(&mut vm, "@repeat(tick, 3)"), Ok(value::Value::Int(3)));
+    assert_eq!(count.get(), 3);
+}
+
+#[test]
+fn test_repeat_negative_count() {
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "noop", vec![], |_, _| Ok(value::Value::None));
+    vm.set_ident(value::Ident("n".to_string()), value::Var {
+        value: value::Value::Int(-1),
+        type_: Type::Int,
+        mutable: true,
+    });
+    assert!(matches!(
+        eval_source(&mut vm, "@repeat(noop, n)"),
+        Err(crate::errors::Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_string_repeat_value() {
+    let s = value::Value::String("ab".to_string());
+    assert_eq!(s.mul(&value::Value::Int(3)), Ok(value::Value::String("ababab".to_string())));
+    assert_eq!(value::Value::Int(3).mul(&s), Ok(value::Value::String("ababab".to_string())));
+}
+
+#[test]
+fn test_string_repeat_zero() {
+    let s = value::Value::String("ab".to_string());
+    assert_eq!(s.mul(&value::Value::Int(0)), Ok(value::Value::String(String::new())));
+}
+
+#[test]
+fn test_string_repeat_large_count() {
+    let s = value::Value::String("ab".to_string());
+    match s.mul(&value::Value::Int(100000)) {
+        Ok(value::Value::String(r)) => {
+            assert_eq!(r.len(), 200000);
+            assert!(r.starts_with("abab") && r.ends_with("abab"));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_string_repeat_invalid_count() {
+    let s = value::Value::String("ab".to_string());
+    assert!(matches!(s.mul(&value::Value::Int(-1)), Err(crate::errors::Error::InvalidArgument(_))));
+    assert!(matches!(s.mul(&value::Value::Float(1.5)), Err(crate::errors::Error::TypeMismatch(_))));
+    assert!(matches!(s.mul(&s), Err(crate::errors::Error::CannotMul(_))));
+}
+
+#[test]
+fn test_times_expr() {
+    assert_expr_eq("times(3) as i { i }", Expr::Block { body: vec![
+        Expr::For {
+            name: Box::new(Expr::Ident { ident: "i".to_string() }),
+            iter: Box::new(Expr::Range {
+                start: Box::new(Expr::Literal { value: Literal::Int(0) }),
+                end: Box::new(Expr::Literal { value: Literal::Int(3) }),
+            }),
+            body: Box::new(Expr::Block { body: vec![
+                Expr::Ident { ident: "i".to_string() }
+            ]}),
+        }
+    ]})
+}
+
+#[test]
+fn test_times_value() {
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let recorder = seen.clone();
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "record", vec!["x"], move |args, _| {
+        recorder.borrow_mut().push(args.get("x").unwrap().value.clone());
+        Ok(value::Value::None)
+    });
+    eval_source(&mut vm, "times(3) as i { @record(i) }").unwrap();
+    assert_eq!(*seen.borrow(), vec![
+        value::Value::Int(0),
+        value::Value::Int(1),
+        value::Value::Int(2),
+    ]);
+}
+
+#[test]
+fn test_pow_syntax() {
+    assert!(tlang::ExprsParser::new().parse("2 ** ").is_err())
+}
+
+#[test]
+fn test_pow_expr() {
+    assert_expr_eq("2 ** 3 ** 2", Expr::Block { body:
+        vec![
+            Expr::BinOp {
+                op: Op::Pow,
+                left: Box::new(Expr::Literal {value: Literal::Int(2)}),
+                right: Box::new(Expr::BinOp {
+                    op: Op::Pow,
+                    left: Box::new(Expr::Literal {value: Literal::Int(3)}),
+                    right: Box::new(Expr::Literal {value: Literal::Int(2)}),
+                })
+            }
+        ]
+    })
+}
+
+#[test]
+fn test_pow_value() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "2 ** 10"), Ok(value::Value::Int(1024)));
+    assert_eq!(eval_source(&mut vm, "2 * 3 ** 2"), Ok(value::Value::Int(18)));
+    assert_eq!(eval_source(&mut vm, "2 ** 3 ** 2"), Ok(value::Value::Int(512)));
+    assert_eq!(
+        value::Value::Int(2).pow(&value::Value::Float(0.5)),
+        Ok(value::Value::Float(2f64.sqrt()))
+    );
+}
+
+#[test]
+fn test_pow_not_a_number() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "'a' ** 2"), Err(crate::errors::Error::CannotPow(_))));
+}
+
+#[test]
+fn test_try_catch_expr() {
+    assert_expr_eq("try { a } catch e { b }", Expr::Block { body: vec![
+        Expr::TryCatch {
+            body: Box::new(Expr::Block { body: vec![Expr::Ident { ident: "a".to_string() }] }),
+            name: "e".to_string(),
+            handler: Box::new(Expr::Block { body: vec![Expr::Ident { ident: "b".to_string() }] }),
+        }
+    ]})
+}
+
+#[test]
+fn test_try_catch_error_kind() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "try { missing } catch e { e->kind }"),
+        Ok(value::Value::String("VarNotFound".to_string()))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "try { missing } catch err { err->message }"),
+        Ok(value::Value::String("Variable missing not found".to_string()))
+    );
+}
+
+#[test]
+fn test_try_catch_match_kind() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "try { 'a' ** 2 } catch e { if e->kind == 'CannotPow' { 1 } else { 2 } }"),
+        Ok(value::Value::Int(1))
+    );
+}
+
+#[test]
+fn test_try_without_error() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "try { 2 ** 2 } catch e { 0 }"), Ok(value::Value::Int(4)));
+}
+
+#[test]
+fn test_block_value() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let y = { let x = 2\nx * 3 }\ny"),
+        Ok(value::Value::Int(6))
+    );
+}
+
+#[test]
+fn test_block_scope() {
+    let mut vm = Vm::new();
+    assert!(matches!(
+        eval_source(&mut vm, "{ let x = 2 }\nx"),
+        Err(crate::errors::Error::VarNotFound(_))
+    ));
+    assert_eq!(
+        eval_source(&mut vm, "let mut a = 1\n{ let b = a\na := b + 1 }\na"),
+        Ok(value::Value::Int(2))
+    );
+}
+
+#[test]
+fn test_loop_and_function_scopes() {
+    let mut vm = Vm::new();
+    for source in [
+        "for i in 0:3 { let t = i }\ni",
+        "for i in 0:3 { let t = i }\nt",
+        "let mut n = 0\nwhile n < 2 { let inner = n\nn += 1 }\ninner",
+        "def f(p) { let local = p }\n@f(1)\nlocal",
+        "def f(p) { p }\n@f(1)\np",
+    ] {
+        assert!(matches!(eval_source(&mut vm, source), Err(crate::errors::Error::VarNotFound(_))), "{}", source);
+    }
+    // Every loop iteration gets a fresh scope, so `let` does not clash with the previous one.
+    assert_eq!(
+        eval_source(&mut vm, "let base = 10
+let mut total = 0
+for i in 0:3 { let t = base + i
+total += t }
+total"),
+        Ok(value::Value::Int(33))
+    );
+}
+
+#[test]
+fn test_div_by_zero() {
+    assert_eq!(
+        value::Value::Int(5).div(&value::Value::Int(0)),
+        Err(crate::errors::Error::DivisionByZero(crate::errors::DivisionByZeroError {
+            left: "5".to_string(),
+        }))
+    );
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "1 / 0"), Err(crate::errors::Error::DivisionByZero(_))));
+}
+
+#[test]
+fn test_mod_by_zero() {
+    assert_eq!(
+        value::Value::Int(5).modulo(&value::Value::Int(0)),
+        Err(crate::errors::Error::DivisionByZero(crate::errors::DivisionByZeroError {
+            left: "5".to_string(),
+        }))
+    );
+    assert_eq!(value::Value::Int(5).modulo(&value::Value::Int(3)), Ok(value::Value::Int(2)));
+}
+
+#[test]
+fn test_try_catch_division_by_zero() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "try { 1 / 0 } catch e { e->kind }"),
+        Ok(value::Value::String("DivisionByZero".to_string()))
+    );
+}
+
+#[cfg(test)]
+fn capture_output(vm: &mut Vm) -> std::rc::Rc<std::cell::RefCell<Vec<u8>>> {
+    let buffer = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    vm.set_output(buffer.clone());
+    buffer
+}
+
+#[test]
+fn test_println_separator() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "@println(1, 2, 3)").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "1 2 3\n");
+}
+
+#[test]
+fn test_print_custom_separator() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    vm.set_separator(", ");
+    eval_source(&mut vm, "@print('a', 'b')").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "a, b");
+}
+
+#[test]
+fn test_neg_expr() {
+    assert_expr_eq("let a = - -3", Expr::Block { body: vec![
+        Expr::Assign {
+            name: "a".to_string(),
+            value: Box::new(Expr::UnaryOp {
+                op: crate::tree::UnaryOp::Neg,
+                value: Box::new(Expr::UnaryOp {
+                    op: crate::tree::UnaryOp::Neg,
+                    value: Box::new(Expr::Literal { value: Literal::Int(3) }),
+                }),
+            }),
+            mutable: false,
+            type_: None,
+        }
+    ]})
+}
+
+#[test]
+fn test_neg_binds_with_subtraction() {
+    assert_expr_eq("a -b", Expr::Block { body: vec![
+        Expr::BinOp {
+            op: Op::Sub,
+            left: Box::new(Expr::Ident { ident: "a".to_string() }),
+            right: Box::new(Expr::Ident { ident: "b".to_string() }),
+        }
+    ]})
+}
+
+#[test]
+fn test_neg_value() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let a = -(1 + 2)\na"), Ok(value::Value::Int(-3)));
+    assert_eq!(eval_source(&mut vm, "let a = - -3\na"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "let a = 5\nlet b = -a\nb"), Ok(value::Value::Int(-5)));
+    assert_eq!(eval_source(&mut vm, "let a = 2 * -3\na"), Ok(value::Value::Int(-6)));
+    assert_eq!(eval_source(&mut vm, "(-2 ** 2)"), Ok(value::Value::Int(-4)));
+}
+
+#[test]
+fn test_neg_string() {
+    let mut vm = Vm::new();
+    assert!(matches!(
+        eval_source(&mut vm, "let a = 'a'\nlet b = -a"),
+        Err(crate::errors::Error::TypeMismatch(_))
+    ));
+}
+
+#[test]
+fn test_call_args_list() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "def f(a, b) { a - b }\n@f(5, -2)"),
+        Ok(value::Value::Int(7))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "[1, -2, 3]"),
+        Ok(value::Value::List(vec![
+            value::Value::Int(1),
+            value::Value::Int(-2),
+            value::Value::Int(3),
+        ]))
+    );
+}
+
+#[test]
+fn test_eprintln_value() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    let err = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    vm.set_error_output(err.clone());
+    eval_source(&mut vm, "@eprintln('oops', 1)\n@print('ok')").unwrap();
+    assert_eq!(String::from_utf8(err.borrow().clone()).unwrap(), "oops 1\n");
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "ok");
+}
+
+#[test]
+fn test_not_expr() {
+    assert_expr_eq("!a", Expr::Block { body: vec![
+        Expr::UnaryOp {
+            op: crate::tree::UnaryOp::Not,
+            value: Box::new(Expr::Ident { ident: "a".to_string() }),
+        }
+    ]})
+}
+
+#[test]
+fn test_not_value() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "!true"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "!(1 == 2)"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "let a = !!false\na"), Ok(value::Value::Bool(false)));
+}
+
+#[test]
+fn test_not_number() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "!5"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_debug_value() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    assert_eq!(
+        eval_source(&mut vm, "@debug([1, 2])"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(2)]))
+    );
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "List[Int(1), Int(2)]\n");
+}
+
+#[test]
+fn test_debug_repr() {
+    assert_eq!(value::Value::String("a".to_string()).debug_repr(), "String(\"a\")");
+    assert_eq!(value::Value::Bool(true).debug_repr(), "Bool(true)");
+    assert_eq!(value::Value::Range(0..3).debug_repr(), "Range(0..3)");
+    assert_eq!(value::Value::None.debug_repr(), "None");
+}
+
+#[test]
+fn test_logic_expr() {
+    assert_expr_eq("a || b && c", Expr::Block { body: vec![
+        Expr::BinOp {
+            op: Op::Or,
+            left: Box::new(Expr::Ident { ident: "a".to_string() }),
+            right: Box::new(Expr::BinOp {
+                op: Op::And,
+                left: Box::new(Expr::Ident { ident: "b".to_string() }),
+                right: Box::new(Expr::Ident { ident: "c".to_string() }),
+            }),
+        }
+    ]})
+}
+
+#[test]
+fn test_logic_value() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "1 < 2 && 2 < 3"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "1 > 2 || 2 > 3"), Ok(value::Value::Bool(false)));
+}
+
+#[test]
+fn test_logic_short_circuit() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "false && missing"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "true || missing"), Ok(value::Value::Bool(true)));
+    assert!(matches!(
+        eval_source(&mut vm, "true && missing"),
+        Err(crate::errors::Error::VarNotFound(_))
+    ));
+}
+
+#[test]
+fn test_logic_left_not_bool() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "5 && true"), Err(crate::errors::Error::TypeMismatch(_))));
+    assert!(matches!(eval_source(&mut vm, "'a' || true"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_string_compare_value() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "'apple' < 'banana'"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "'apple' > 'banana'"), Ok(value::Value::Bool(false)));
+}
+
+#[test]
+fn test_string_compare_equal() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "'abc' < 'abc'"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "'abc' <= 'abc'"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "'abc' >= 'abc'"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_string_compare_prefix() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "'ab' < 'abc'"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "'abc' > 'ab'"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_string_compare_case_sensitive() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "'Banana' < 'apple'"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "'a' > 'A'"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_string_compare_mixed() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "'a' < 1"), Err(crate::errors::Error::CannotCompare(_))));
+}
+
+#[test]
+fn test_get_ident_by_str() {
+    let mut vm = Vm::new();
+    vm.set_ident(value::Ident("answer".to_string()), value::Var {
+        value: value::Value::Int(42),
+        type_: Type::Int,
+        mutable: false,
+    });
+    assert_eq!(vm.get_ident("answer").map(|var| var.value.clone()), Some(value::Value::Int(42)));
+    assert!(vm.exists("answer"));
+    assert!(vm.get_ident("missing").is_none());
+}
+
+#[test]
+fn test_call_in_loop_value() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "def inc(x) { x + 1 }\nlet mut n = 0\nfor i in 0:100 { n := @inc(n) }\nn"),
+        Ok(value::Value::Int(100))
+    );
+    assert!(matches!(eval_source(&mut vm, "@nope(1)"), Err(crate::errors::Error::FunctionNotFound(_))));
+}
+
+#[test]
+fn test_list_equality() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "[1, 2] == [1, 2]"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "[1, 2] == [2, 1]"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "[1, 2] != [1, 2]"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "[] == []"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_nested_list_equality() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "[[1, 2], ['a']] == [[1, 2], ['a']]"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "[[1, 2], ['a']] == [[1, 3], ['a']]"), Ok(value::Value::Bool(false)));
+}
+
+#[test]
+fn test_list_equality_different_lengths() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "[1, 2] == [1, 2, 3]"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "[1, 2] != [1]"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_range_equality() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "0:3 == 0:3"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "0:3 != 0:4"), Ok(value::Value::Bool(true)));
+}
+
+#[test]
+fn test_scope_pooling_matches_unpooled() {
+    let source = "def add(a, b) { a + b }
+let mut total = 0
+for i in 0:50 { if i > 10 { total := @add(total, i) } }
+total";
+    let mut plain = Vm::new();
+    let mut pooled = Vm::new_with_capacity(32);
+    pooled.set_scope_pooling(true);
+    let expected = eval_source(&mut plain, source);
+    assert_eq!(expected, Ok(value::Value::Int(1170)));
+    assert_eq!(eval_source(&mut pooled, source), expected);
+    assert_eq!(eval_source(&mut pooled, source), expected);
+}
+
+#[test]
+fn test_integer_arithmetic_stays_integer() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "5 / 2"), Ok(value::Value::Int(2)));
+    assert_eq!(eval_source(&mut vm, "7 / 3 + 2 * 4 - 1"), Ok(value::Value::Int(9)));
+    assert_eq!(eval_source(&mut vm, "2 ** 10"), Ok(value::Value::Int(1024)));
+    assert_eq!(eval_source(&mut vm, "let l = [1, 2, 3]\nl.2"), Ok(value::Value::Int(3)));
+}
+
+#[test]
+fn test_mixed_arithmetic_promotes_to_float() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "5 / 2.0"), Ok(value::Value::Float(2.5)));
+    assert_eq!(eval_source(&mut vm, "1 + 0.5"), Ok(value::Value::Float(1.5)));
+    assert_eq!(eval_source(&mut vm, "2 ** -1"), Ok(value::Value::Float(0.5)));
+    assert_eq!(eval_source(&mut vm, "1 == 1.0"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "(3 to float)"), Ok(value::Value::Float(3.)));
+    assert_eq!(value::Value::Float(3.).display_value(), "3.0");
+}
+
+#[test]
+fn test_integer_overflow_errors() {
+    assert!(value::Value::Int(i64::MAX).add(&value::Value::Int(1)).is_err());
+    assert!(value::Value::Int(2).pow(&value::Value::Int(64)).is_err());
+}
+
+#[test]
+fn test_assert_type_matching() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@assert_type([1, 2], 'list')"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(2)]))
+    );
+    assert_eq!(eval_source(&mut vm, "@assert_type(2.5, 'float') + 1"), Ok(value::Value::Float(3.5)));
+}
+
+#[test]
+fn test_assert_type_mismatch() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@assert_type('abc', 'list')"),
+        Err(crate::errors::Error::TypeMismatch(crate::errors::TypeMismatchError {
+            expected: value::Type::List,
+            found: value::Type::String,
+        }))
+    );
+}
+
+#[test]
+fn test_if_truthy_number() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "if 1 { 'yes' } else { 'no' }"), Ok(value::Value::String("yes".to_string())));
+    assert_eq!(eval_source(&mut vm, "if 0 { 'yes' } else { 'no' }"), Ok(value::Value::String("no".to_string())));
+    assert_eq!(eval_source(&mut vm, "if '' { 'yes' } else { 'no' }"), Ok(value::Value::String("no".to_string())));
+}
+
+#[test]
+fn test_while_empty_list_terminates() {
+    let mut vm = Vm::new();
+    let ran = std::rc::Rc::new(std::cell::Cell::new(false));
+    let flag = ran.clone();
+    define_host_function(&mut vm, "mark", vec![], move |_, _| {
+        flag.set(true);
+        Ok(value::Value::None)
+    });
+    assert_eq!(eval_source(&mut vm, "while [] { @mark() }"), Ok(value::Value::None));
+    assert!(!ran.get());
+}
+
+#[test]
+fn test_truthiness_rules() {
+    assert!(!value::Value::None.is_truthy());
+    assert!(!value::Value::List(vec![]).is_truthy());
+    assert!(value::Value::List(vec![value::Value::Int(0)]).is_truthy());
+    assert!(value::Value::String("a".to_string()).is_truthy());
+    assert!(!value::Value::Float(0.).is_truthy());
+}
+
+#[test]
+fn test_repeat_over_max_length_errors() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "'x' * 10000000000"),
+        Err(crate::errors::Error::MaxLengthExceeded(crate::errors::MaxLengthExceededError {
+            limit: 1 << 26,
+            length: 10000000000,
+        }))
+    );
+}
+
+#[test]
+fn test_configured_max_length() {
+    let mut vm = Vm::new();
+    vm.set_max_length(4);
+    assert_eq!(eval_source(&mut vm, "'ab' * 2"), Ok(value::Value::String("abab".to_string())));
+    assert!(matches!(eval_source(&mut vm, "'ab' * 3"), Err(crate::errors::Error::MaxLengthExceeded(_))));
+    assert!(matches!(eval_source(&mut vm, "[1, 2, 3, 4, 5]"), Err(crate::errors::Error::MaxLengthExceeded(_))));
+}
+
+#[test]
+fn test_compound_repeat_respects_max_length() {
+    let mut vm = Vm::new();
+    vm.set_max_length(1000);
+    let source = "let mut s = 'ab' s *= 100000";
+    assert!(matches!(eval_source(&mut vm, source), Err(crate::errors::Error::MaxLengthExceeded(_))));
+    let ast = tlang::ExprsParser::new().parse(source).unwrap();
+    assert!(matches!(
+        vm.run_chunk(&crate::executer::bytecode::compile(&ast)),
+        Err(crate::errors::Error::MaxLengthExceeded(_))
+    ));
+}
+
+#[test]
+fn test_break_stops_loop() {
+    let mut vm = Vm::new();
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let recorder = seen.clone();
+    define_host_function(&mut vm, "record", vec!["x"], move |args, _| {
+        recorder.borrow_mut().push(args.get("x").unwrap().value.clone());
+        Ok(value::Value::None)
+    });
+    eval_source(&mut vm, "for i in 0:10 { if i == 5 { break }\n@record(i) }").unwrap();
+    assert_eq!(seen.borrow().len(), 5);
+}
+
+#[test]
+fn test_continue_skips_rest_of_body() {
+    let mut vm = Vm::new();
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let recorder = seen.clone();
+    define_host_function(&mut vm, "record", vec!["x"], move |args, _| {
+        recorder.borrow_mut().push(args.get("x").unwrap().value.clone());
+        Ok(value::Value::None)
+    });
+    eval_source(&mut vm, "for i in 0:4 { if i == 2 { continue }\n@record(i) }").unwrap();
+    assert_eq!(
+        *seen.borrow(),
+        vec![value::Value::Int(0), value::Value::Int(1), value::Value::Int(3)]
+    );
+}
+
+#[test]
+fn test_break_in_while() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "while true { break }\n1"), Ok(value::Value::Int(1)));
+    assert_eq!(eval_source(&mut vm, "try { break } catch e { 0 }"), Err(crate::errors::Error::Break));
+}
+
+#[test]
+fn test_destructure_divmod() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let (q, r) = @divmod(7, 2)\n[q, r]"),
+        Ok(value::Value::List(vec![value::Value::Int(3), value::Value::Int(1)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@divmod(7.5, 2)"),
+        Ok(value::Value::Tuple(vec![value::Value::Float(3.), value::Value::Float(1.5)]))
+    );
+}
+
+#[test]
+fn test_destructure_arity_mismatch() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let (a, b, c) = @divmod(7, 2)"),
+        Err(crate::errors::Error::DestructureMismatch(crate::errors::DestructureMismatchError {
+            expected: 3,
+            found: 2,
+        }))
+    );
+}
+
+#[test]
+fn test_early_return_from_loop() {
+    let mut vm = Vm::new();
+    let source = "def first_over(l, n) { for x in l { if x > n { return x } }\n0 }
+[@first_over([1, 5, 9, 12], 4), @first_over([1, 2], 4)]";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Ok(value::Value::List(vec![value::Value::Int(5), value::Value::Int(0)]))
+    );
+}
+
+#[test]
+fn test_return_skips_rest_of_function() {
+    let mut vm = Vm::new();
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = calls.clone();
+    define_host_function(&mut vm, "tick", vec![], move |_, _| {
+        counter.set(counter.get() + 1);
+        Ok(value::Value::None)
+    });
+    let source = "def f() { while true { if true { return 'done' } }\n@tick() }
+@f()";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::String("done".to_string())));
+    assert_eq!(calls.get(), 0);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex_matches() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@matches('abc123', '[0-9]+')"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "@matches('abc', '[0-9]+')"), Ok(value::Value::Bool(false)));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex_find_all() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@find_all('a1b22c333', '[0-9]+')"),
+        Ok(value::Value::List(vec![
+            value::Value::String("1".to_string()),
+            value::Value::String("22".to_string()),
+            value::Value::String("333".to_string()),
+        ]))
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex_invalid_pattern() {
+    let mut vm = Vm::new();
+    assert!(matches!(
+        eval_source(&mut vm, "@matches('abc', '[0-9')"),
+        Err(crate::errors::Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_functions_call_later_definitions() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "def a() { @b() }\ndef b() { 1 }\n@a()"), Ok(value::Value::Int(1)));
+}
+
+#[test]
+fn test_mutually_recursive_functions() {
+    let mut vm = Vm::new();
+    let source = "def is_even(n) { if n == 0 { true } else { @is_odd(n - 1) } }
+def is_odd(n) { if n == 0 { false } else { @is_even(n - 1) } }
+[@is_even(4), @is_odd(3), @is_even(3)]";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Ok(value::Value::List(vec![
+            value::Value::Bool(true),
+            value::Value::Bool(true),
+            value::Value::Bool(false),
+        ]))
+    );
+}
+
+#[test]
+fn test_function_reads_top_level_constant() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "const limit = 10\ndef get() { limit }\n@get()"), Ok(value::Value::Int(10)));
+}
+
+#[test]
+fn test_function_does_not_see_caller_locals() {
+    let mut vm = Vm::new();
+    assert!(matches!(
+        eval_source(&mut vm, "def get() { secret }\ndef caller() { let secret = 1\n@get() }\n@caller()"),
+        Err(crate::errors::Error::VarNotFound(_))
+    ));
+}
+
+#[test]
+fn test_function_updates_captured_variable() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let mut count = 0\ndef bump() { count := count + 1 }\n@bump()\n@bump()\ncount"),
+        Ok(value::Value::Int(2))
+    );
+}
+
+#[test]
+fn test_method_sees_globals() {
+    let mut vm = Vm::new();
+    let source = "const scale = 3
+struct P { x }
+impl P def scaled() { self->x * scale }
+let p = @P{ x => 2 }
+p->@scaled()";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::Int(6)));
+}
+
+#[test]
+fn test_method_binds_its_own_parameters() {
+    let mut vm = Vm::new();
+    // `y` names both a field and a parameter; the argument binds to the parameter.
+    let source = "struct P { x; y }
+impl P def scaled(k, y) { self->x * k + self->y + y }
+let p = @P{ x => 3; y => 1 }
+p->@scaled(10, 100)";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::Int(131)));
+}
+
+#[test]
+fn test_method_argument_count_mismatch() {
+    let mut vm = Vm::new();
+    let source = "struct P { x }\nimpl P def scaled(k) { self->x * k }\nlet p = @P{ x => 3 }\n";
+    for (call, found) in [("p->@scaled()", 0), ("p->@scaled(1, 2)", 2)] {
+        assert_eq!(
+            eval_source(&mut vm, &format!("{}{}", source, call)),
+            Err(crate::errors::Error::FunctionArgumentMismatch(crate::errors::FunctionArgumentMismatchError {
+                name: "scaled".to_string(),
+                expected: 1,
+                found,
+            }))
+        );
+    }
+}
+
+#[test]
+fn test_pad_left() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@pad_left('42', 5)"), Ok(value::Value::String("   42".to_string())));
+    assert_eq!(eval_source(&mut vm, "@pad_left('42', 5, '0')"), Ok(value::Value::String("00042".to_string())));
+    assert_eq!(eval_source(&mut vm, "@pad_left('toolong', 3)"), Ok(value::Value::String("toolong".to_string())));
+}
+
+#[test]
+fn test_pad_right() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@pad_right('ab', 4)"), Ok(value::Value::String("ab  ".to_string())));
+    assert_eq!(eval_source(&mut vm, "@pad_right('ab', 4, '.')"), Ok(value::Value::String("ab..".to_string())));
+    assert!(matches!(
+        eval_source(&mut vm, "@pad_right('ab', 4, '..')"),
+        Err(crate::errors::Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_mutating_immutable_var_errors() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let a = 1\na := 2"),
+        Err(crate::errors::Error::CannotMutate(crate::errors::CannotMutateError {
+            var_name: "a".to_string(),
+        }))
+    );
+    assert!(matches!(eval_source(&mut vm, "const b = 1\nb += 1"), Err(crate::errors::Error::CannotMutate(_))));
+}
+
+#[test]
+fn test_mut_var_can_be_reassigned() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let mut a = 1\na := 2\na"), Ok(value::Value::Int(2)));
+    assert_eq!(eval_source(&mut vm, "let mut b = 1\nb += 4\nb *= 3\nb"), Ok(value::Value::Int(15)));
+}
+
+#[test]
+fn test_eval_many_returns_value_per_statement() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.eval_many("let a = 2\na * 3"),
+        Ok(vec![value::Value::None, value::Value::Int(6)])
+    );
+    assert_eq!(vm.eval_many("a + 1"), Ok(vec![value::Value::Int(3)]));
+}
+
+#[test]
+fn test_eval_many_parse_error() {
+    let mut vm = Vm::new();
+    assert!(matches!(vm.eval_many("let = 5"), Err(crate::errors::Error::Parse(_))));
+}
+
+#[test]
+fn test_let_redefines_name() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let a = 1\nlet a = 'two'\na"), Ok(value::Value::String("two".to_string())));
+    assert_eq!(eval_source(&mut vm, "let a = 1\n{ let a = 2 }\na"), Ok(value::Value::Int(1)));
+}
+
+#[test]
+fn test_let_redefinition_and_mutable_flag() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let a = 1\nlet mut a = a + 1\na := a * 10\na"), Ok(value::Value::Int(20)));
+    assert!(matches!(
+        eval_source(&mut vm, "let mut a = 1\nlet a = 2\na := 3"),
+        Err(crate::errors::Error::CannotMutate(_))
+    ));
+}
+
+#[test]
+fn test_shadowing_disabled() {
+    let mut vm = Vm::new();
+    vm.set_shadowing(false);
+    assert!(matches!(
+        eval_source(&mut vm, "let a = 1\nlet a = 2"),
+        Err(crate::errors::Error::VarAlreadyDefined(_))
+    ));
+}
+
+#[test]
+fn test_profile_report() {
+    let mut vm = Vm::new();
+    vm.set_profiling(true);
+    eval_source(&mut vm, "let mut i = 0\nwhile i < 20 { i := i + 1 }").unwrap();
+    let report = vm.profile_report();
+    let entry = |kind: &str| report.iter().find(|(k, _)| *k == kind).map(|(_, e)| *e).unwrap();
+    assert_eq!(entry("While").count, 1);
+    assert!(entry("While").total > std::time::Duration::ZERO);
+    assert_eq!(entry("BinOp").count, 41);
+    assert!(entry("BinOp").total > std::time::Duration::ZERO);
+}
+
+#[test]
+fn test_profile_disabled_by_default() {
+    let mut vm = Vm::new();
+    eval_source(&mut vm, "1 + 1").unwrap();
+    assert!(vm.profile_report().is_empty());
+}
+
+#[test]
+fn test_get_attr_after_construction() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "struct Point { x; y }\nlet p = @Point{ x => 1; y => 'b' }\n[p->x, p->y]"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::String("b".to_string())]))
+    );
+}
+
+#[test]
+fn test_get_attr_errors() {
+    let mut vm = Vm::new();
+    assert!(matches!(
+        eval_source(&mut vm, "struct Point { x; y }\nlet p = @Point{ x => 1; y => 2 }\np->z"),
+        Err(crate::errors::Error::AttrNotFound(_))
+    ));
+    assert_eq!(
+        eval_source(&mut vm, "let n = 1\nn->x"),
+        Err(crate::errors::Error::TypeMismatch(crate::errors::TypeMismatchError {
+            expected: value::Type::Struct("n".to_string()),
+            found: value::Type::Int,
+        }))
+    );
+}
+
+#[cfg(test)]
+fn define_none(vm: &mut Vm, name: &str) {
+    vm.set_ident(value::Ident(name.to_string()), value::Var {
+        value: value::Value::None,
+        type_: Type::None,
+        mutable: false,
+    });
+}
+
+#[test]
+fn test_optional_chaining_on_none() {
+    let mut vm = Vm::new();
+    define_none(&mut vm, "none_value");
+    assert_eq!(eval_source(&mut vm, "none_value?->field"), Ok(value::Value::None));
+    assert_eq!(eval_source(&mut vm, "none_value?->@method(@missing())"), Ok(value::Value::None));
+    assert!(matches!(eval_source(&mut vm, "none_value->field"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_optional_chaining_on_struct() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "struct P { x }\nimpl P def get() { self->x }\nlet p = @P{ x => 4 }\n[p?->x, p?->@get()]"),
+        Ok(value::Value::List(vec![value::Value::Int(4), value::Value::Int(4)]))
+    );
+}
+
+#[test]
+fn test_println_struct() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "struct Point { y; x }\nlet p = @Point{ y => 2; x => 'a' }\n@println(p)\n@println(Point)").unwrap();
+    assert_eq!(
+        String::from_utf8(out.borrow().clone()).unwrap(),
+        "Point { x: a, y: 2 }\nstruct Point { y, x }\n"
+    );
+}
+
+#[test]
+fn test_println_enum() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "enum Color { Red; Green }\n@println(@Color{Green})\n@println(Color)").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "Color::Green\nenum { Red, Green }\n");
+}
+
+#[test]
+fn test_println_range() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "@println(0:3)").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "0..3\n");
+}
+
+#[test]
+fn test_list_spread() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "[0, ...[1, 2], 3]"),
+        Ok(value::Value::List(vec![
+            value::Value::Int(0),
+            value::Value::Int(1),
+            value::Value::Int(2),
+            value::Value::Int(3),
+        ]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "let xs = [1]\n[...xs, ...[], ...xs]"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(1)]))
+    );
+}
+
+#[test]
+fn test_list_spread_non_list() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "[...5]"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_match_literal_cases() {
+    let mut vm = Vm::new();
+    let source = "def describe(n) { match n { 1 => 'one', 2 => 'two', _ => 'many' } }
+[@describe(1), @describe(2), @describe(7)]";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Ok(value::Value::List(vec![
+            value::Value::String("one".to_string()),
+            value::Value::String("two".to_string()),
+            value::Value::String("many".to_string()),
+        ]))
+    );
+}
+
+#[test]
+fn test_match_uses_current_scope() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let offset = 10\nlet two = 2\nmatch 2 { 1 => 0, two => offset + 2 }"),
+        Ok(value::Value::Int(12))
+    );
+    assert_eq!(eval_source(&mut vm, "match 'x' { 1 => 'num', 'y' => 'y' }"), Ok(value::Value::None));
+}
+
+#[test]
+fn test_host_defined_struct() {
+    let mut vm = Vm::new();
+    vm.define_struct("Point", &["x", "y"]);
+    assert_eq!(
+        eval_source(&mut vm, "let p = @Point{ x => 1; y => 2 }\np->x + p->y"),
+        Ok(value::Value::Int(3))
+    );
+}
+
+#[test]
+fn test_host_defined_function() {
+    let mut vm = Vm::new();
+    vm.define_function("double", &["n"], |args, _| args["n"].value.mul(&value::Value::Int(2)));
+    assert_eq!(eval_source(&mut vm, "@double(21)"), Ok(value::Value::Int(42)));
+}
+
+#[test]
+fn test_len_list() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@len([1, 2, 3])"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "@len([])"), Ok(value::Value::Int(0)));
+}
+
+#[test]
+fn test_len_string_counts_chars() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@len('abc')"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "@len('héé')"), Ok(value::Value::Int(3)));
+}
+
+#[test]
+fn test_len_range() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@len(2:7)"), Ok(value::Value::Int(5)));
+    assert_eq!(eval_source(&mut vm, "@len(5:2)"), Ok(value::Value::Int(3)));
+}
+
+#[test]
+fn test_len_unsupported_type() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "@len(5)"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_type_info_homogeneous_list() {
+    let mut vm = Vm::new();
+    let list = eval_source(&mut vm, "[1, 2, 3]").unwrap();
+    assert_eq!(list.type_info(), value::TypeInfo::List(Box::new(value::TypeInfo::Simple(value::Type::Int))));
+    assert_eq!(list.type_info().name(), "list<int>");
+}
+
+#[test]
+fn test_type_info_mixed_and_nested() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "[1, 'a']").unwrap().type_info().name(), "list<mixed>");
+    assert_eq!(eval_source(&mut vm, "[]").unwrap().type_info().name(), "list<unknown>");
+    assert_eq!(eval_source(&mut vm, "[[1], [2, 3]]").unwrap().type_info().name(), "list<list<int>>");
+    assert_eq!(
+        eval_source(&mut vm, "struct P { x; tags }\n@P{ x => 1.5; tags => ['a'] }").unwrap().type_info().name(),
+        "P { tags: list<string>, x: float }"
+    );
+}
+
+#[test]
+fn test_is_empty_builtins() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@is_empty([])").unwrap(), value::Value::Bool(true));
+    assert_eq!(eval_source(&mut vm, "@is_empty([1])").unwrap(), value::Value::Bool(false));
+    assert_eq!(eval_source(&mut vm, "@non_empty('')").unwrap(), value::Value::Bool(false));
+    assert_eq!(eval_source(&mut vm, "@non_empty('a')").unwrap(), value::Value::Bool(true));
+    assert_eq!(eval_source(&mut vm, "@is_empty(0:0)").unwrap(), value::Value::Bool(true));
+    assert!(matches!(eval_source(&mut vm, "@is_empty(1)"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_push_and_pop() {
+    let mut vm = Vm::new();
+    vm.set_ident(value::Ident("xs".to_string()), value::Var { value: value::Value::List(vec![]), type_: value::Type::List, mutable: true });
+    eval_source(&mut vm, "@push('xs', 1) @push('xs', 2) @push('xs', 3)").unwrap();
+    assert_eq!(
+        vm.get_ident("xs").unwrap().value,
+        value::Value::List(vec![value::Value::Int(1), value::Value::Int(2), value::Value::Int(3)])
+    );
+    assert_eq!(eval_source(&mut vm, "@pop('xs')").unwrap(), value::Value::Int(3));
+    eval_source(&mut vm, "@pop('xs') @pop('xs')").unwrap();
+    assert_eq!(vm.get_ident("xs").unwrap().value, value::Value::List(vec![]));
+    assert!(matches!(eval_source(&mut vm, "@pop('xs')"), Err(crate::errors::Error::InvalidArgument(_))));
+}
+
+#[test]
+fn test_push_requires_mutable_list() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "let xs = [] @push('xs', 1)"), Err(crate::errors::Error::CannotMutate(_))));
+    assert!(matches!(eval_source(&mut vm, "@push('nope', 1)"), Err(crate::errors::Error::VarNotFound(_))));
+}
+
+#[test]
+fn test_swap() {
+    let mut vm = Vm::new();
+    let result = eval_source(&mut vm, "let mut a = 1 let mut b = 2 swap(a, b) [a, b]").unwrap();
+    assert_eq!(result, value::Value::List(vec![value::Value::Int(2), value::Value::Int(1)]));
+    assert!(matches!(eval_source(&mut vm, "let mut a = 1 swap(a, b)"), Err(crate::errors::Error::VarNotFound(_))));
+    assert!(matches!(eval_source(&mut vm, "let a = 1 let mut b = 2 swap(a, b)"), Err(crate::errors::Error::CannotMutate(_))));
+}
+
+#[test]
+fn test_range_builtin_with_step() {
+    let mut vm = Vm::new();
+    let ints = |v: Vec<i64>| value::Value::List(v.into_iter().map(value::Value::Int).collect());
+    assert_eq!(eval_source(&mut vm, "@range(0, 10, 2)").unwrap(), ints(vec![0, 2, 4, 6, 8]));
+    assert_eq!(eval_source(&mut vm, "@range(0, 3)").unwrap(), ints(vec![0, 1, 2]));
+    assert_eq!(eval_source(&mut vm, "@range(5, 0, -2)").unwrap(), ints(vec![5, 3, 1]));
+    assert_eq!(eval_source(&mut vm, "@range(0, 3, 10)").unwrap(), ints(vec![0]));
+    assert_eq!(eval_source(&mut vm, "@range(3, 0)").unwrap(), ints(vec![]));
+    assert!(matches!(eval_source(&mut vm, "@range(0, 3, 0)"), Err(crate::errors::Error::InvalidArgument(_))));
+}
+
+#[test]
+fn test_struct_update() {
+    let mut vm = Vm::new();
+    let result = eval_source(
+        &mut vm,
+        "struct P { x; y; z }\nlet p = @P{ x => 1; y => 2; z => 3 }\nlet q = @P{ ...p; y => 5 }\n[p->y, q->x, q->y, q->z]",
+    )
+    .unwrap();
+    assert_eq!(
+        result,
+        value::Value::List(vec![value::Value::Int(2), value::Value::Int(1), value::Value::Int(5), value::Value::Int(3)])
+    );
+}
+
+#[test]
+fn test_struct_update_requires_same_struct() {
+    let mut vm = Vm::new();
+    let result = eval_source(&mut vm, "struct P { x }\nstruct Q { x }\nlet q = @Q{ x => 1 }\n@P{ ...q; x => 2 }");
+    assert!(matches!(result, Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_struct_fields_evaluated_once() {
+    let count = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = count.clone();
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "tick", vec![], move |_, _| {
+        counter.set(counter.get() + 1);
+        Ok(value::Value::Int(counter.get() as i64))
+    });
+    assert_eq!(eval_source(&mut vm, "struct P { x; y }\nlet p = @P{ x => @tick(); y => 0 }\np->x"), Ok(value::Value::Int(1)));
+    assert_eq!(count.get(), 1);
+    assert_eq!(
+        eval_source(&mut vm, "struct P { x; y }\n@P{ x => 1; w => 2 }"),
+        Err(crate::errors::Error::AttrNotFound(crate::errors::AttrNotFoundError { attr_name: "w".to_string() }))
+    );
+}
+
+#[test]
+fn test_for_descending_range() {
+    let mut vm = Vm::new();
+    let result = eval_source(&mut vm, "let mut xs = [] for i in 3:0 { xs := [...xs, i] } xs").unwrap();
+    assert_eq!(
+        result,
+        value::Value::List(vec![value::Value::Int(3), value::Value::Int(2), value::Value::Int(1)])
+    );
+    assert_eq!(eval_source(&mut vm, "@len((5:0))"), Ok(value::Value::Int(5)));
+    assert_eq!(eval_source(&mut vm, "@is_empty((5:0))"), Ok(value::Value::Bool(false)));
+    assert_eq!(eval_source(&mut vm, "@is_empty((2:2))"), Ok(value::Value::Bool(true)));
+    assert_eq!(eval_source(&mut vm, "if 5:0 { 'yes' } else { 'no' }"), Ok(value::Value::String("yes".to_string())));
+}
+
+#[test]
+fn test_if_else_builtin() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@if_else(1 < 2, 'yes', 'no')").unwrap(), value::Value::String("yes".to_string()));
+    assert_eq!(eval_source(&mut vm, "@if_else(false, 'yes', 'no')").unwrap(), value::Value::String("no".to_string()));
+    assert!(matches!(eval_source(&mut vm, "@if_else(1, 2, 3)"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_if_else_evaluates_both_branches() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "@if_else(true, @print('a'), @print('b'))").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "ab");
+}
+
+#[test]
+fn test_custom_operator() {
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "dot", vec!["a", "b"], |args, _| {
+        match (&args["a"].value, &args["b"].value) {
+            (value::Value::List(a), value::Value::List(b)) => {
+                let mut sum = value::Value::Int(0);
+                for (x, y) in a.iter().zip(b) {
+                    sum = sum.add(&x.mul(y)?)?;
+                }
+                Ok(sum)
+            }
+            _ => Ok(value::Value::None),
+        }
+    });
+    vm.define_operator("dot", "dot");
+    let result = eval_source(&mut vm, "let a = [1, 2, 3] let b = [4, 5, 6] a `dot` b").unwrap();
+    assert_eq!(result, value::Value::Int(32));
+    assert!(matches!(eval_source(&mut vm, "a `cross` b"), Err(crate::errors::Error::OperatorNotFound(_))));
+}
+
+#[test]
+fn test_string_index() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let s = 'hello' s.1").unwrap(), value::Value::String("e".to_string()));
+    assert_eq!(eval_source(&mut vm, "let s = 'hello' s.(1:3)").unwrap(), value::Value::String("el".to_string()));
+    assert!(matches!(eval_source(&mut vm, "let s = 'hello' s.5"), Err(crate::errors::Error::IndexOutOfBounds(_))));
+    assert!(matches!(eval_source(&mut vm, "let s = 'hello' s.(2:9)"), Err(crate::errors::Error::IndexOutOfBounds(_))));
+    assert!(matches!(eval_source(&mut vm, "let s = 'hello' s.(3:1)"), Err(crate::errors::Error::IndexOutOfBounds(_))));
+}
+
+#[test]
+fn test_slice_rejects_descending_and_negative_ranges() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "let xs = [1, 2, 3] xs.(2:1)"), Err(crate::errors::Error::IndexOutOfBounds(_))));
+    vm.set_ident(value::Ident("r".to_string()), value::Var {
+        value: value::Value::Range(-1..2),
+        type_: Type::Range,
+        mutable: false,
+    });
+    assert_eq!(
+        eval_source(&mut vm, "let xs = [1, 2, 3] xs.(r)"),
+        Err(crate::errors::Error::IndexOutOfBounds(crate::errors::IndexOutOfBoundsError {
+            index: -1,
+            name: "xs".to_string(),
+        }))
+    );
+}
+
+#[test]
+fn test_ast_round_trip() {
+    let source = "struct P { x; y }\nimpl P def sum() { self->x + self->y }\nlet mut total = 0.5\nfor i in 0:4 { total += i }\nlet p = @P{ x => 1; y => -2 }\nlet q = @P{ ...p; y => 3 }\nmatch q->@sum() { 4 => [total to int, 'ok', !false], _ => 'no' }";
+    let ast = tlang::ExprsParser::new().parse(source).unwrap();
+    let decoded = crate::serialize::deserialize_ast(&crate::serialize::serialize_ast(&ast)).unwrap();
+    assert_eq!(decoded, ast);
+    assert_eq!(Vm::new().eval_expr(decoded).unwrap(), Vm::new().eval_expr(ast).unwrap());
+}
+
+#[test]
+fn test_ast_deserialize_rejects_truncated_input() {
+    let ast = tlang::ExprsParser::new().parse("let a = 'hello'").unwrap();
+    let bytes = crate::serialize::serialize_ast(&ast);
+    assert!(matches!(crate::serialize::deserialize_ast(&bytes[..bytes.len() - 1]), Err(crate::errors::Error::Parse(_))));
+    assert!(crate::serialize::deserialize_ast(&[]).is_err());
+}
+
+#[test]
+fn test_ast_deserialize_rejects_deep_nesting() {
+    let mut nested = Expr::Literal { value: crate::tree::Literal::Int(1) };
+    for _ in 0..100 {
+        nested = Expr::UnaryOp { op: crate::tree::UnaryOp::Neg, value: Box::new(nested) };
+    }
+    let bytes = crate::serialize::serialize_ast(&nested);
+    assert_eq!(crate::serialize::deserialize_ast(&bytes), Ok(nested));
+
+    // A `Neg` unary operator, over and over.
+    let mut bytes = vec![bytes[0]];
+    for _ in 0..200_000 {
+        bytes.extend_from_slice(&[7, 0]);
+    }
+    assert_eq!(
+        crate::serialize::deserialize_ast(&bytes),
+        Err(crate::errors::Error::Parse(crate::errors::ParseError { message: "AST nested too deeply".to_string() }))
+    );
+}
+
+#[test]
+fn test_index_literal_list() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "[4, 5, 6].1").unwrap(), value::Value::Int(5));
+    assert_eq!(eval_source(&mut vm, "def f() { [7, 8] } @f().0").unwrap(), value::Value::Int(7));
+}
+
+#[test]
+fn test_nested_index() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let m = [[1, 2], [3, 4]] m.1.(0)").unwrap(), value::Value::Int(3));
+    assert_eq!(eval_source(&mut vm, "[[1, 2], [3, 4]].0.(1)").unwrap(), value::Value::Int(2));
+    assert!(matches!(eval_source(&mut vm, "let n = 1 n.0"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[cfg(test)]
+fn assert_bytecode_matches(source: &str) {
+    let ast = tlang::ExprsParser::new().parse(source).unwrap();
+    let chunk = crate::executer::bytecode::compile(&ast);
+    let mut tree_vm = Vm::new();
+    let tree_out = capture_output(&mut tree_vm);
+    let mut chunk_vm = Vm::new();
+    let chunk_out = capture_output(&mut chunk_vm);
+    let tree = tree_vm.eval_expr(ast);
+    assert_eq!(chunk_vm.run_chunk(&chunk), tree, "{}", source);
+    assert_eq!(*chunk_out.borrow(), *tree_out.borrow(), "{}", source);
+}
+
+#[test]
+fn test_bytecode_matches_tree_walker() {
+    let programs = [
+        "1 + 2 * 3 - 4 / 2",
+        "let mut total = 0 for i in 0:10 { total += i * i } total",
+        "let mut n = 0 while n < 5 { n := n + 1 } n",
+        "let mut xs = [] for i in 5:0 { if i == 3 { continue } xs := [...xs, i] } xs",
+        "let mut n = 0 while true { n += 1 if n > 3 { break } } n",
+        "for i in [1, 2, 3] { i * 10 }",
+        "if 0 { 'a' } else { 'b' }",
+        "if false { 1 }",
+        "true && false || 1 < 2",
+        "let a = 2 { let a = 3 } a",
+        "def sq(x) { x * x } @println(@sq(4), @sq(5)) @sq(6)",
+        "struct P { x } let p = @P{ x => 7 } match p->x { 7 => 'seven', _ => 'other' }",
+        "let s = 'ab' s * 3",
+        "let mut i = 0 while i < 3 { i += 1 match i { 2 => break, _ => 0 } } i",
+        "let total = 0 total := 1",
+        "@missing(1)",
+        "1 / 0",
+        "let (a, b) = @divmod(7, 2) [a, b]",
+        "for i in 0:3 { @print(i) }",
+    ];
+    for program in programs {
+        assert_bytecode_matches(program);
+    }
+}
+
+#[test]
+fn test_bytecode_restores_scopes_on_error() {
+    let ast = tlang::ExprsParser::new().parse("{ { 1 / 0 } }").unwrap();
+    let mut vm = Vm::new();
+    let outer = vm.clone();
+    assert!(vm.run_chunk(&crate::executer::bytecode::compile(&ast)).is_err());
+    // The clone shares only the outer scope, so this is visible to it only if the blocks were popped.
+    vm.set_ident(value::Ident("x".to_string()), value::Var { value: value::Value::Int(1), type_: value::Type::Int, mutable: false });
+    assert_eq!(outer.get_ident("x").unwrap().value, value::Value::Int(1));
+}
+
+#[test]
+fn test_bytecode_for_variable_is_scoped() {
+    let ast = tlang::ExprsParser::new().parse("for i in 0:3 { if i == 1 { break } }").unwrap();
+    let mut vm = Vm::new();
+    assert_eq!(vm.run_chunk(&crate::executer::bytecode::compile(&ast)), Ok(value::Value::None));
+    assert!(vm.get_ident("i").is_none());
+}
+
+#[test]
+fn test_for_variable_takes_item_type() {
+    let source = "let mut out = [] for s in ['a', 'b'] { s := 'seen' out := [...out, s] } out";
+    let mut vm = Vm::new();
+    let seen = value::Value::String("seen".to_string());
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::List(vec![seen.clone(), seen])));
+    assert_bytecode_matches(source);
+}
+
+#[test]
+fn test_map_literal_and_lookup() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let m = #{ 'a' => 1, 'b' => 2 } m.('b')").unwrap(), value::Value::Int(2));
+    assert_eq!(eval_source(&mut vm, "let k = 3 let m = #{ k => 'c' } m.k").unwrap(), value::Value::String("c".to_string()));
+    assert_eq!(eval_source(&mut vm, "#{ 'b' => [1], 'a' => true }").unwrap().to_string(), "{a: true, b: [1]}");
+    assert_eq!(eval_source(&mut vm, "@len(#{})").unwrap(), value::Value::Int(0));
+}
+
+#[test]
+fn test_map_insert() {
+    let mut vm = Vm::new();
+    let result = eval_source(&mut vm, "let mut m = #{ 'a' => 1 } m := @insert(m, 'b', 2) m := @insert(m, 'a', 3) m").unwrap();
+    assert_eq!(result.to_string(), "{a: 3, b: 2}");
+    assert_eq!(eval_source(&mut vm, "#{ 'a' => 1 } == #{ 'a' => 1 }").unwrap(), value::Value::Bool(true));
+}
+
+#[test]
+fn test_map_missing_key() {
+    let mut vm = Vm::new();
+    assert!(matches!(eval_source(&mut vm, "let m = #{ 'a' => 1 } m.('z')"), Err(crate::errors::Error::KeyNotFound(_))));
+    assert!(matches!(eval_source(&mut vm, "#{ [1] => 1 }"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_bytecode_constant_pool_dedupes_literals() {
+    let ast = tlang::ExprsParser::new().parse("let a = 'hello' let b = 'hello' [a, b, 'hello', 1, 1, 1.5, 1.5]").unwrap();
+    let chunk = crate::executer::bytecode::compile(&ast);
+    assert_eq!(chunk.pool_size(), 3);
+    assert_eq!(chunk.constants.iter().filter(|c| **c == value::Value::String("hello".to_string())).count(), 1);
+}
+
+#[test]
+fn test_tuple_literals() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "()").unwrap(), value::Value::Tuple(vec![]));
+    assert_eq!(eval_source(&mut vm, "()").unwrap().to_string(), "()");
+    let t = eval_source(&mut vm, "(1, 'a', true)").unwrap();
+    assert_eq!(t.to_string(), "(1, a, true)");
+    assert_eq!(t.get_type(), value::Type::Tuple);
+    assert_eq!(eval_source(&mut vm, "let t = (1, 'a', true) t.1").unwrap(), value::Value::String("a".to_string()));
+    assert_eq!(eval_source(&mut vm, "let t = (1, 'a', true) t.(1:3)").unwrap().to_string(), "(a, true)");
+    assert_eq!(eval_source(&mut vm, "let (x, y) = (4, 5) x + y").unwrap(), value::Value::Int(9));
+    assert_eq!(eval_source(&mut vm, "(7,)").unwrap(), value::Value::Tuple(vec![value::Value::Int(7)]));
+}
+
+#[test]
+fn test_tuple_equality() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "(1, 'a') == (1, 'a')").unwrap(), value::Value::Bool(true));
+    assert_eq!(eval_source(&mut vm, "(1, 'a') == (1, 'b')").unwrap(), value::Value::Bool(false));
+    assert_eq!(eval_source(&mut vm, "(1, 2) != (1, 2, 3)").unwrap(), value::Value::Bool(true));
+    assert_eq!(eval_source(&mut vm, "() == ()").unwrap(), value::Value::Bool(true));
+}
+
+#[test]
+fn test_bytecode_peephole_removes_no_ops() {
+    let ast = tlang::ExprsParser::new().parse("let x = 5 (x + 0) * 1 - --x").unwrap();
+    let chunk = crate::executer::bytecode::compile(&ast);
+    let binaries = chunk.code.iter().filter(|i| matches!(i, crate::executer::bytecode::Instr::Binary(_))).count();
+    let unaries = chunk.code.iter().filter(|i| matches!(i, crate::executer::bytecode::Instr::Unary(_))).count();
+    assert_eq!((binaries, unaries), (1, 0));
+    assert_eq!(Vm::new().run_chunk(&chunk).unwrap(), value::Value::Int(0));
+    for program in ["let x = 2.5 x * 1 + 0", "let s = 'ab' s * 1", "!!true", "let x = 1.5 --x"] {
+        assert_bytecode_matches(program);
+    }
+}
+
+#[test]
+fn test_bytecode_peephole_keeps_errors() {
+    for program in ["'a' + 0", "[1] / 1", "true - 0", "!!3", "let y = --'a'", "let x = 0 - 9223372036854775807 - 1 let y = --x"] {
+        assert_bytecode_matches(program);
+        assert!(Vm::new().eval_expr(tlang::ExprsParser::new().parse(program).unwrap()).is_err(), "{}", program);
+    }
+}
+
+#[test]
+fn test_pmap_matches_map() {
+    let mut vm = Vm::new();
+    let source = "def f(x) { if x < 100 { (x, 'fizz') } else { (x * x, [x, 1.5]) } } let xs = @range(0, 500)";
+    let mapped = eval_source(&mut vm, &format!("{} @map(xs, f)", source)).unwrap();
+    assert_eq!(eval_source(&mut vm, &format!("{} @pmap(xs, f)", source)).unwrap(), mapped);
+    assert_eq!(eval_source(&mut vm, &format!("{} @len(@pmap(xs, f))", source)).unwrap(), value::Value::Int(500));
+    assert_eq!(eval_source(&mut vm, &format!("{} @pmap([], f)", source)).unwrap(), value::Value::List(vec![]));
+}
+
+#[test]
+fn test_pmap_falls_back_for_impure_functions() {
+    let mut vm = Vm::new();
+    let source = "let k = 10 def add_k(x) { x + k } def fact(n) { if n < 2 { 1 } else { n * @fact(n - 1) } }";
+    assert_eq!(eval_source(&mut vm, &format!("{} @pmap([1, 2], add_k)", source)).unwrap().to_string(), "[11, 12]");
+    assert_eq!(eval_source(&mut vm, &format!("{} @pmap([3, 5], fact)", source)).unwrap().to_string(), "[6, 120]");
+    assert!(matches!(eval_source(&mut vm, "def bad(x) { x / 0 } @pmap([1], bad)"), Err(crate::errors::Error::DivisionByZero(_))));
+}
+
+#[test]
+fn test_pmap_keeps_the_callers_environment() {
+    let mut vm = Vm::new();
+    let out = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    vm.set_output(out.clone());
+    assert_eq!(
+        eval_source(&mut vm, "def f(x) { @println(x) x * 2 } @pmap([1, 2, 3], f)").unwrap().to_string(),
+        "[2, 4, 6]"
+    );
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "1\n2\n3\n");
+
+    // Printing before failing happens once, as with `map`.
+    out.borrow_mut().clear();
+    assert!(matches!(
+        eval_source(&mut vm, "def g(x) { @println(x) x / 0 } @pmap([1], g)"),
+        Err(crate::errors::Error::DivisionByZero(_))
+    ));
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "1\n");
+
+    define_host_function(&mut vm, "abs", vec!["n"], |_, _| Ok(value::Value::Int(7)));
+    assert_eq!(eval_source(&mut vm, "def h(x) { @abs(x) } @pmap([0 - 1, 2], h)").unwrap().to_string(), "[7, 7]");
+
+    vm.set_precision(Some(2));
+    assert_eq!(
+        eval_source(&mut vm, "def s(x) { @format('{}', x) } @pmap([1.5], s)"),
+        Ok(value::Value::List(vec![value::Value::String("1.50".to_string())]))
+    );
+
+    vm.define_operator("at_least", "max");
+    assert_eq!(eval_source(&mut vm, "def k(x) { x `at_least` 2 } @pmap([1, 3], k)").unwrap().to_string(), "[2, 3]");
+}
+
+#[test]
+fn test_string_escapes() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, r"'line1\nline2'"), Ok(value::Value::String("line1\nline2".to_string())));
+    assert_eq!(eval_source(&mut vm, r"'a\tb\r\\c'"), Ok(value::Value::String("a\tb\r\\c".to_string())));
+    assert_eq!(eval_source(&mut vm, r#"'it\'s \"quoted\"'"#), Ok(value::Value::String("it's \"quoted\"".to_string())));
+}
+
+#[test]
+fn test_string_unknown_escape() {
+    let err = tlang::ExprsParser::new().parse(r"'a\qb'").unwrap_err();
+    assert!(err.to_string().contains(r"unknown escape sequence \q"), "{}", err);
+}
+
+#[test]
+fn test_program_edit_reruns_only_affected_statements() {
+    let mut vm = Vm::new();
+    let calls = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let record = calls.clone();
+    define_host_function(&mut vm, "tick", vec!["label", "value"], move |args, _vm| {
+        record.borrow_mut().push(args["label"].value.to_string());
+        Ok(args["value"].value.clone())
+    });
+    let mut program = crate::executer::incremental::Program::parse(
+        "let a = @tick('a', 1)\nlet b = @tick('b', a + 1)\nlet c = @tick('c', 10)\nlet d = @tick('d', c + b)\nd",
+    ).unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[4], value::Value::Int(12));
+    assert_eq!(*calls.borrow(), vec!["a", "b", "c", "d"]);
+
+    calls.borrow_mut().clear();
+    program.edit(3, "let d = @tick('d', c * b)").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[4], value::Value::Int(20));
+    assert_eq!(*calls.borrow(), vec!["d"]);
+
+    calls.borrow_mut().clear();
+    program.edit(0, "let a = @tick('a', 4)").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[4], value::Value::Int(50));
+    assert_eq!(*calls.borrow(), vec!["a", "b", "d"]);
+    assert_eq!(program.results()[1], value::Value::None);
+}
+
+#[test]
+fn test_program_edit_reruns_the_binding_of_updated_names() {
+    let mut vm = Vm::new();
+    let mut program = crate::executer::incremental::Program::parse("let mut x = 0\nx += 1\nx").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[2], value::Value::Int(1));
+    program.edit(1, "x += 2").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[2], value::Value::Int(2));
+
+    // An update that reruns because of another name starts over from the binding too.
+    let mut program = crate::executer::incremental::Program::parse("let mut x = 0\nlet y = 1\nx += y\nx").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[3], value::Value::Int(1));
+    program.edit(1, "let y = 2").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[3], value::Value::Int(2));
+
+    // Replacing an update undoes it.
+    program.edit(2, "let z = 0").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap()[3], value::Value::Int(0));
+}
+
+#[test]
+fn test_program_edit_errors() {
+    let mut program = crate::executer::incremental::Program::parse("let a = 1\na").unwrap();
+    assert_eq!(program.len(), 2);
+    assert!(!program.is_empty());
+    assert!(matches!(program.edit(0, "let a = 1 let b = 2"), Err(crate::errors::Error::Parse(_))));
+    assert!(matches!(program.edit(5, "a"), Err(crate::errors::Error::IndexOutOfBounds(_))));
+    let mut vm = Vm::new();
+    program.edit(1, "a / 0").unwrap();
+    assert!(matches!(vm.run_program(&mut program), Err(crate::errors::Error::DivisionByZero(_))));
+    program.edit(1, "a + 1").unwrap();
+    assert_eq!(vm.run_program(&mut program).unwrap(), vec![value::Value::None, value::Value::Int(2)]);
+}
+
+#[test]
+fn test_format_source_is_idempotent() {
+    let programs = [
+        "let mut total = 0 for i in 0:10 { total += i * i } total",
+        "def f(a, b) { if a > b { return a - b } else { b } } @println(@f(3, 1))",
+        "/* setup */ let xs = [1, ...[2, 3]] let t = (1,) let m = #{ 'a\\n' => (1, 2) } xs.(0:2) m.('a\\n').1",
+        "struct P { x; y } impl P def norm() { self->x * self->x } let p = @P{ x => 1; y => 2 } let q = @P{ ...p; y => 5 } q->@norm()",
+        "enum Color { Red; Green } match @Color{Red} { @Color{Red} => 'red', _ => 'other' }",
+        "let a = (1 + 2) * -(3 - 4) let b = 2 ** (1 + 1) let c = (a == b) && (!(1 < 2)) || false",
+        "let grid = [[1, 2], [3, 4]] grid.1.(0) times(3) as i { while false { } } try { 1 / 0 } catch e { e }",
+        "let x: int = 1.5 to int swap(x, x) let (p, q) = @divmod(7, 2) x := x - 1 x `dot` p",
+        "// header\nlet a = 1 // one\n{ a // inner\n} let b = [1, // dropped\n2]",
+        "let s = 'not // a comment' /* block // text */ s // end",
+    ];
+    for program in programs {
+        let once = crate::format::format_source(program).unwrap();
+        assert_eq!(crate::format::format_source(&once).unwrap(), once, "{}", program);
+        assert_eq!(tlang::ExprsParser::new().parse(&once), tlang::ExprsParser::new().parse(program), "{}", once);
+    }
+}
+
+#[test]
+fn test_format_source_layout() {
+    let formatted = crate::format::format_source("/* sum */\nlet mut n = 0 for i in 0:3 { n += i /* step */\n} n").unwrap();
+    assert_eq!(formatted, "/* sum */\nlet mut n = 0\nfor i in 0:3 {\n    n += i\n    /* step */\n}\nn\n");
+    let formatted = crate::format::format_source("let a = 1 // one\n{ a // inner\n}").unwrap();
+    assert_eq!(formatted, "let a = 1\n// one\n{\n    a\n    // inner\n}\n");
+    assert!(matches!(crate::format::format_source("let = 1"), Err(crate::errors::Error::Parse(_))));
+}
+
+#[test]
+fn test_line_comments() {
+    let mut vm = Vm::new();
+    let plain = "let a = 2\nlet b = [a, 3]\na * b.1";
+    let commented = "// setup\nlet a = 2 // two\n// the list\nlet b = [a, // first\n3]\na * b.1 // result";
+    assert_eq!(eval_source(&mut vm, commented), eval_source(&mut vm, plain));
+    assert_eq!(eval_source(&mut vm, "{ 1 // trailing\n}"), Ok(value::Value::Int(1)));
+    assert_eq!(eval_source(&mut vm, "'a // b' // c"), Ok(value::Value::String("a // b".to_string())));
+    assert_eq!(eval_source(&mut vm, "4 / 2 // halved"), Ok(value::Value::Int(2)));
+}
+
+#[test]
+fn test_multi_let() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let a = 1, b = a + 1, c: int = b * 3 (a, b, c)").unwrap().to_string(), "(1, 2, 6)");
+    assert_eq!(eval_source(&mut vm, "let mut a = 1, b = 2 a := 5 b += a b"), Ok(value::Value::Int(7)));
+    assert!(matches!(eval_source(&mut vm, "let a = 1, b = 2 b := 3"), Err(crate::errors::Error::CannotMutate(_))));
+    assert_eq!(eval_source(&mut vm, "@len([let a = 1, 2])"), Ok(value::Value::Int(2)));
+    assert_bytecode_matches("let x = 2, y = x * x, z = y - x z");
+    let formatted = crate::format::format_source("let mut a = 1, b: int = a").unwrap();
+    assert_eq!(formatted, "let mut a = 1, b: int = a\n");
+}
+
+#[test]
+fn test_else_if_chain() {
+    let mut vm = Vm::new();
+    let chain = "def pick(n) { if n < 0 { 'neg' } else if n == 0 { 'zero' } else { 'pos' } }";
+    for (n, expected) in [("0 - 3", "neg"), ("0", "zero"), ("7", "pos")] {
+        let source = format!("{} @pick({})", chain, n);
+        assert_eq!(eval_source(&mut vm, &source), Ok(value::Value::String(expected.to_string())));
+    }
+    assert_eq!(eval_source(&mut vm, "if false { 1 } else if false { 2 }"), Ok(value::Value::None));
+
+    // Only the first branch whose condition holds runs.
+    let mut vm = Vm::new();
+    let calls = std::rc::Rc::new(std::cell::RefCell::new(0));
+    let counter = calls.clone();
+    define_host_function(&mut vm, "hit", vec![], move |_, _| {
+        *counter.borrow_mut() += 1;
+        Ok(value::Value::Bool(true))
+    });
+    assert_eq!(eval_source(&mut vm, "if @hit() { 1 } else if @hit() { 2 } else { 3 }"), Ok(value::Value::Int(1)));
+    assert_eq!(*calls.borrow(), 1);
+
+    let source = "if a { 1 } else if b { 2 } else if c { 3 } else { 4 }";
+    let formatted = crate::format::format_source(source).unwrap();
+    assert_eq!(tlang::ExprsParser::new().parse(&formatted), tlang::ExprsParser::new().parse(source));
+    assert_bytecode_matches("let n = 5 if n < 3 { 'a' } else if n < 6 { 'b' } else { 'c' }");
+}
+
+#[test]
+fn test_do_expression() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "1 + do { let x = 2; x * 3 }"), Ok(value::Value::Int(7)));
+    assert_eq!(eval_source(&mut vm, "let y = do { let a = 4\na + 1 } * 2 y"), Ok(value::Value::Int(10)));
+    assert!(matches!(eval_source(&mut vm, "let z = do { let x = 2; x } x"), Err(crate::errors::Error::VarNotFound(_))));
+    assert_eq!(eval_source(&mut vm, "do { }"), Ok(value::Value::None));
+    let formatted = crate::format::format_source("1 + do { let x = 2; x * 3 }").unwrap();
+    assert_eq!(formatted, "1 + do {\n    let x = 2\n    x * 3\n}\n");
+}
+
+#[test]
+fn test_number_format() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "@print(1234567)").unwrap();
+    vm.set_thousands_separator(true);
+    eval_source(&mut vm, "@print(' ', 1234567, -1234, 999, [1000, 2.5])").unwrap();
+    vm.set_precision(Some(2));
+    eval_source(&mut vm, "@print(' ', 1234.5, 0.125)").unwrap();
+    vm.set_thousands_separator(false);
+    vm.set_precision(None);
+    eval_source(&mut vm, "@print(' ', 1234567, 1234.5)").unwrap();
+    assert_eq!(
+        String::from_utf8(out.borrow().clone()).unwrap(),
+        "1234567  1,234,567 -1,234 999 [1,000, 2.5]  1,234.50 0.12  1234567 1234.5"
+    );
+}
+
+#[test]
+fn test_lambda() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@(fn(x) => x + 1)(2)"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "let add = fn(a, b) => a + b @add(2, 5)"), Ok(value::Value::Int(7)));
+    assert_eq!(
+        eval_source(&mut vm, "let n = 10 let f = fn(x) => { let y = x * 2\ny + n } @f(1)"),
+        Ok(value::Value::Int(12))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@map([1, 2, 3], fn(x) => x * x)"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(4), value::Value::Int(9)]))
+    );
+    assert!(matches!(
+        eval_source(&mut vm, "@(fn(x) => x)()"),
+        Err(crate::errors::Error::FunctionArgumentMismatch(_))
+    ));
+    let formatted = crate::format::format_source("let f = fn(x) => x + 1\n@(f)(2)").unwrap();
+    assert_eq!(formatted, "let f = fn(x) => x + 1\n@(f)(2)\n");
+}
+
+#[test]
+fn test_min_max() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@max('apple', 'banana')"), Ok(value::Value::String("banana".to_string())));
+    assert_eq!(eval_source(&mut vm, "@min('apple', 'banana')"), Ok(value::Value::String("apple".to_string())));
+    assert_eq!(eval_source(&mut vm, "@max(2, 1.5)"), Ok(value::Value::Int(2)));
+    assert_eq!(eval_source(&mut vm, "@min(2, 1.5)"), Ok(value::Value::Float(1.5)));
+    assert!(matches!(eval_source(&mut vm, "@max(1, 'a')"), Err(crate::errors::Error::CannotCompare(_))));
+}
+
+#[test]
+fn test_min_max_over_lists_and_varargs() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@max([3, 9, 2.5, 4])"), Ok(value::Value::Int(9)));
+    assert_eq!(eval_source(&mut vm, "@min([3, 9, 2.5, 4])"), Ok(value::Value::Float(2.5)));
+    assert_eq!(eval_source(&mut vm, "@min(5, 3, 8)"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "@max(['b', 'c', 'a'])"), Ok(value::Value::String("c".to_string())));
+    assert_eq!(eval_source(&mut vm, "@max(7)"), Ok(value::Value::Int(7)));
+    assert!(matches!(eval_source(&mut vm, "@min([])"), Err(crate::errors::Error::InvalidArgument(_))));
+    assert!(matches!(eval_source(&mut vm, "@max()"), Err(crate::errors::Error::InvalidArgument(_))));
+    assert!(matches!(eval_source(&mut vm, "@min([1, 'a'])"), Err(crate::errors::Error::CannotCompare(_))));
+}
+
+#[test]
+fn test_map_with_lambda() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@map([1, 2, 3], fn(x) => x * 2)"),
+        Ok(value::Value::List(vec![value::Value::Int(2), value::Value::Int(4), value::Value::Int(6)]))
+    );
+    assert_eq!(eval_source(&mut vm, "@map([], fn(x) => x * 2)"), Ok(value::Value::List(vec![])));
+    assert!(matches!(
+        eval_source(&mut vm, "@map([1, 0], fn(x) => 1 / x)"),
+        Err(crate::errors::Error::DivisionByZero(_))
+    ));
+    assert!(matches!(eval_source(&mut vm, "@map([1], 2)"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_index_assign_past_end_errors_by_default() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let mut xs = [1, 2, 3] xs.1 := 7 xs"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(7), value::Value::Int(3)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "let mut xs = [1, 2, 3] xs.5 := 9"),
+        Err(crate::errors::Error::IndexOutOfBounds(crate::errors::IndexOutOfBoundsError {
+            index: 5,
+            name: "xs".to_string(),
+        }))
+    );
+    assert!(matches!(
+        eval_source(&mut vm, "let xs = [1, 2, 3] xs.0 := 9"),
+        Err(crate::errors::Error::CannotMutate(_))
+    ));
+}
+
+#[test]
+fn test_index_assign_past_end_grows_list() {
+    let mut vm = Vm::new();
+    vm.set_list_growth(true);
+    assert_eq!(
+        eval_source(&mut vm, "let mut xs = [1, 2, 3] let i = 5 xs.(i) := 9 xs"),
+        Ok(value::Value::List(vec![
+            value::Value::Int(1),
+            value::Value::Int(2),
+            value::Value::Int(3),
+            value::Value::None,
+            value::Value::None,
+            value::Value::Int(9),
+        ]))
+    );
+    assert_eq!(crate::format::format_source("xs.(i) := 9").unwrap(), "xs.i := 9\n");
+}
+
+#[test]
+fn test_filter_and_reduce() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@filter([1, 2, 3, 4, 5, 6], fn(x) => (x / 2 * 2) == x)"),
+        Ok(value::Value::List(vec![value::Value::Int(2), value::Value::Int(4), value::Value::Int(6)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@filter([0, 1, 2], fn(x) => x)"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::Int(2)]))
+    );
+    assert_eq!(eval_source(&mut vm, "@reduce([1, 2, 3, 4], fn(acc, x) => acc + x, 0)"), Ok(value::Value::Int(10)));
+    assert_eq!(eval_source(&mut vm, "@reduce([2, 3, 4], fn(acc, x) => acc * x, 1)"), Ok(value::Value::Int(24)));
+    assert_eq!(eval_source(&mut vm, "@reduce([], fn(acc, x) => acc + x, 7)"), Ok(value::Value::Int(7)));
+}
+
+#[test]
+fn test_name_checks_warn_on_undefined_reference() {
+    let mut vm = Vm::new();
+    let err = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    vm.set_error_output(err.clone());
+    vm.set_name_checks(true);
+    let source = "
+let scale = 2
+def is_even(n) { if n == 0 { true } else { @is_odd(n - 1) } }
+def is_odd(n) { if n == 0 { false } else { @is_even(n - 1) } }
+def area(w) { let h = w * scale\n@println(h + depth) }
+let f = fn(x) => x + offset
+@is_even(4)";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::Bool(true)));
+    assert_eq!(
+        String::from_utf8(err.borrow().clone()).unwrap(),
+        "warning: function area uses undefined name depth\nwarning: function <lambda> uses undefined name offset\n"
+    );
+}
+
+#[test]
+fn test_sort() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@sort([3, 1.5, 2, -4])"),
+        Ok(value::Value::List(vec![
+            value::Value::Int(-4),
+            value::Value::Float(1.5),
+            value::Value::Int(2),
+            value::Value::Int(3),
+        ]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@sort(['pear', 'apple', 'fig'])"),
+        Ok(value::Value::List(vec![
+            value::Value::String("apple".to_string()),
+            value::Value::String("fig".to_string()),
+            value::Value::String("pear".to_string()),
+        ]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@sort([1, 3, 2], fn(a, b) => a > b)"),
+        Ok(value::Value::List(vec![value::Value::Int(3), value::Value::Int(2), value::Value::Int(1)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "let xs = [2, 1] @sort(xs) xs"),
+        Ok(value::Value::List(vec![value::Value::Int(2), value::Value::Int(1)]))
+    );
+    assert!(matches!(eval_source(&mut vm, "@sort([1, 'a', 2])"), Err(crate::errors::Error::CannotCompare(_))));
+}
+
+#[test]
+fn test_with_closes_resource() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    let source = "struct File { path }
+impl File def close() { @println('closed', self->path) }
+with f = @File{ path => 'a.txt' } { @println('using', f->path)\nf->path }";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::String("a.txt".to_string())));
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "using a.txt\nclosed a.txt\n");
+}
+
+#[test]
+fn test_with_closes_resource_when_body_errors() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    let source = "struct File { path }
+impl File def close() { @println('closed', self->path) }
+with f = @File{ path => 'b.txt' } { 1 / 0 }";
+    assert!(matches!(eval_source(&mut vm, source), Err(crate::errors::Error::DivisionByZero(_))));
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "closed b.txt\n");
+    assert_eq!(eval_source(&mut vm, "with n = 3 { n * 2 }"), Ok(value::Value::Int(6)));
+}
+
+#[test]
+fn test_math_builtins() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "let a = -5 @abs(a)"), Ok(value::Value::Int(5)));
+    assert_eq!(eval_source(&mut vm, "let a = -2.5 @abs(a)"), Ok(value::Value::Float(2.5)));
+    assert_eq!(eval_source(&mut vm, "@sqrt(9)"), Ok(value::Value::Float(3.0)));
+    assert_eq!(eval_source(&mut vm, "@sqrt(2.25)"), Ok(value::Value::Float(1.5)));
+    assert!(matches!(
+        eval_source(&mut vm, "let a = -1 @sqrt(a)"),
+        Err(crate::errors::Error::InvalidArgument(_))
+    ));
+    assert_eq!(eval_source(&mut vm, "@floor(2.7)"), Ok(value::Value::Float(2.0)));
+    assert_eq!(eval_source(&mut vm, "let a = -2.2 @floor(a)"), Ok(value::Value::Float(-3.0)));
+    assert_eq!(eval_source(&mut vm, "@ceil(2.2)"), Ok(value::Value::Float(3.0)));
+    assert_eq!(eval_source(&mut vm, "@round(2.5)"), Ok(value::Value::Float(3.0)));
+    assert_eq!(eval_source(&mut vm, "@round(4)"), Ok(value::Value::Int(4)));
+    assert_eq!(eval_source(&mut vm, "@round(2.5, 'half_up')"), Ok(value::Value::Float(3.0)));
+    assert_eq!(eval_source(&mut vm, "@round(2.5, 'half_even')"), Ok(value::Value::Float(2.0)));
+    assert_eq!(eval_source(&mut vm, "@round(3.5, 'half_even')"), Ok(value::Value::Float(4.0)));
+    assert!(matches!(eval_source(&mut vm, "@round(2.5, 'down')"), Err(crate::errors::Error::InvalidArgument(_))));
+    assert_eq!(
+        eval_source(&mut vm, "@floor('x')"),
+        Err(crate::errors::Error::TypeMismatch(crate::errors::TypeMismatchError {
+            expected: value::Type::Float,
+            found: value::Type::String,
+        }))
+    );
+}
+
+#[test]
+fn test_assert_raises() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "let e = @assert_raises(fn() => 1 / 0) e->kind"),
+        Ok(value::Value::String("DivisionByZero".to_string()))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@assert_raises(fn() => 1 + 1)"),
+        Err(crate::errors::Error::AssertionFailed(crate::errors::AssertionFailedError {
+            message: "expected an error, got Int(2)".to_string(),
+        }))
+    );
+}
+
+#[test]
+fn test_format_renders_like_print() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    let source = "let n = if false { 1 }\n@print(@format('{} and {} in {{{}}}', true, n, [false, n]), n)";
+    eval_source(&mut vm, source).unwrap();
+    vm.set_empty_none(true);
+    eval_source(&mut vm, &format!("@print('|')\n{}", source)).unwrap();
+    assert_eq!(
+        String::from_utf8(out.borrow().clone()).unwrap(),
+        "true and None in {[false, None]} None|true and  in {[false, None]} "
+    );
+    assert!(matches!(eval_source(&mut vm, "@format('{}')"), Err(crate::errors::Error::InvalidArgument(_))));
+    assert!(matches!(eval_source(&mut vm, "@format('{', 1)"), Err(crate::errors::Error::InvalidArgument(_))));
+}
+
+#[test]
+fn test_type_builtin() {
+    let mut vm = Vm::new();
+    let type_of = |vm: &mut Vm, src: &str| match eval_source(vm, src) {
+        Ok(value::Value::String(s)) => s,
+        other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(type_of(&mut vm, "@type(1)"), "Int");
+    assert_eq!(type_of(&mut vm, "@type(1.5)"), "Float");
+    assert_eq!(type_of(&mut vm, "@type([1, 2])"), "List");
+    assert_eq!(type_of(&mut vm, "@type(fn(x) => x)"), "Func");
+    assert_eq!(
+        eval_source(&mut vm, "struct Point { x; y }\nlet p = @Point{ x => 1; y => 2 }\n[@type(Point), @type(p)]"),
+        Ok(value::Value::List(vec![
+            value::Value::String("Struct(Point)".to_string()),
+            value::Value::String("FieldStruct(Point)".to_string()),
+        ]))
+    );
+}
+
+#[test]
+fn test_recursion_limit() {
+    let mut vm = Vm::new();
+    let out = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    vm.set_recursion_limit(3).set_max_length(100).set_output(out.clone());
+    let source = "def down(n) { if n == 0 { 0 } else { @down(n - 1) } }\n@println(@down(2))\n@down(5)";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Err(crate::errors::Error::RecursionLimitExceeded(crate::errors::RecursionLimitExceededError { limit: 3 }))
+    );
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "0\n");
+}
+
+#[test]
+fn test_step_limit() {
+    let mut vm = Vm::new();
+    vm.set_step_limit(1000);
+    assert!(eval_source(&mut vm, "let mut i = 0 while i < 10 { i += 1 } i").is_ok());
+    assert_eq!(
+        eval_source(&mut vm, "let mut i = 0 while true { i += 1 }"),
+        Err(crate::errors::Error::StepLimitExceeded(crate::errors::StepLimitExceededError { limit: 1000 }))
+    );
+}
+
+#[test]
+fn test_step_limit_covers_bytecode_and_pmap() {
+    let exceeded = Err(crate::errors::Error::StepLimitExceeded(crate::errors::StepLimitExceededError { limit: 1000 }));
+    let ast = tlang::ExprsParser::new().parse("while true { }").unwrap();
+    let mut vm = Vm::new();
+    vm.set_step_limit(1000);
+    assert_eq!(vm.run_chunk(&crate::executer::bytecode::compile(&ast)), exceeded);
+    let mut vm = Vm::new();
+    vm.set_step_limit(1000);
+    assert_eq!(eval_source(&mut vm, "def f(x) { while true { } } @pmap([1], f)"), exceeded);
+}
+
+#[test]
+fn test_eval_with_timeout() {
+    let mut vm = Vm::new();
+    let timeout = std::time::Duration::from_millis(50);
+    assert_eq!(
+        vm.eval_with_timeout("let mut i = 0 while i < 10 { i += 1 } i", timeout),
+        Ok(vec![value::Value::None, value::Value::None, value::Value::Int(10)])
+    );
+    assert_eq!(
+        vm.eval_with_timeout("while true { }", timeout),
+        Err(crate::errors::Error::Timeout(crate::errors::TimeoutError { timeout }))
+    );
+    assert_eq!(
+        vm.eval_with_timeout("def spin(x) { while true { } } @pmap([1, 2], spin)", timeout),
+        Err(crate::errors::Error::Timeout(crate::errors::TimeoutError { timeout }))
+    );
+    // The deadline only applies to the call that set it.
+    assert_eq!(eval_source(&mut vm, "i"), Ok(value::Value::Int(10)));
+}
+
+#[test]
+fn test_conversion_builtins() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@str(1.0)"), Ok(value::Value::String("1.0".to_string())));
+    assert_eq!(eval_source(&mut vm, "@str([1, true])"), Ok(value::Value::String("[1, true]".to_string())));
+    assert_eq!(eval_source(&mut vm, "@int('12')"), Ok(value::Value::Int(12)));
+    assert_eq!(eval_source(&mut vm, "@int(3.9)"), Ok(value::Value::Int(3)));
+    assert_eq!(eval_source(&mut vm, "@float('2.5')"), Ok(value::Value::Float(2.5)));
+    assert_eq!(eval_source(&mut vm, "@float(2)"), Ok(value::Value::Float(2.0)));
+    assert_eq!(
+        eval_source(&mut vm, "@int('abc')"),
+        Err(crate::errors::Error::InvalidCastNumber(crate::errors::InvalidCastNumberError {
+            elt: "abc".to_string(),
+        }))
+    );
+    assert!(matches!(eval_source(&mut vm, "@float([1])"), Err(crate::errors::Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_deep_clone_is_independent() {
+    let shared = value::Value::List(vec![value::Value::Int(1), value::Value::Int(2)]);
+    let mut fields = std::collections::HashMap::new();
+    fields.insert(value::Ident("items".to_string()), shared.clone());
+    let original = value::Value::List(vec![
+        shared.clone(),
+        shared,
+        value::Value::CallStruct { name: "Bag".to_string(), fields },
+    ]);
+    let mut copy = original.deep_clone();
+    assert_eq!(copy, original);
+    if let value::Value::List(items) = &mut copy {
+        items[0] = value::Value::None;
+        if let value::Value::List(inner) = &mut items[1] {
+            inner.push(value::Value::Int(3));
+        }
+        if let value::Value::CallStruct { fields, .. } = &mut items[2] {
+            fields.clear();
+        }
+    }
+    let shared = value::Value::List(vec![value::Value::Int(1), value::Value::Int(2)]);
+    match &original {
+        value::Value::List(items) => {
+            assert_eq!(items[0], shared);
+            assert_eq!(items[1], shared);
+            assert!(matches!(&items[2], value::Value::CallStruct { fields, .. } if fields.len() == 1));
+        }
+        _ => unreachable!(),
+    }
+    assert_ne!(copy, original);
+}
+
+#[test]
+fn test_input_reads_lines_until_eof() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    vm.set_input(std::rc::Rc::new(std::cell::RefCell::new(std::io::Cursor::new(b"Ada\r\n42\n".to_vec()))));
+    assert_eq!(
+        eval_source(&mut vm, "[@input('name? '), @read('age? '), @input('more? '), @read('')]"),
+        Ok(value::Value::List(vec![
+            value::Value::String("Ada".to_string()),
+            value::Value::String("42".to_string()),
+            value::Value::None,
+            value::Value::String("".to_string()),
+        ]))
+    );
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "name? age? more? ");
+}
+
+#[test]
+fn test_help_lists_and_describes_builtins() {
+    let mut vm = Vm::new();
+    let names = match eval_source(&mut vm, "@help()") {
+        Ok(value::Value::List(names)) => names,
+        other => panic!("unexpected result {:?}", other),
+    };
+    for name in ["print", "println", "help"] {
+        assert!(names.contains(&value::Value::String(name.to_string())));
+    }
+    assert!(names.windows(2).all(|w| w[0].display_value() < w[1].display_value()));
+    assert_eq!(
+        eval_source(&mut vm, "@help('println')"),
+        Ok(value::Value::String("writes its arguments joined by the separator, then a newline".to_string()))
+    );
+    assert_eq!(eval_source(&mut vm, "@help('nope')"), Ok(value::Value::None));
+}
+
+#[test]
+fn test_assert() {
+    let mut vm = Vm::new();
+    assert_eq!(eval_source(&mut vm, "@assert(1 < 2)"), Ok(value::Value::None));
+    assert_eq!(eval_source(&mut vm, "@assert([0], 'unused')"), Ok(value::Value::None));
+    assert_eq!(
+        eval_source(&mut vm, "@assert(2 < 1)"),
+        Err(crate::errors::Error::AssertionFailed(crate::errors::AssertionFailedError {
+            message: "Bool(false) is not truthy".to_string(),
+        }))
+    );
+    let err = eval_source(&mut vm, "let total = 3\n@assert(total == 4, 'total should be 4')").unwrap_err();
+    assert_eq!(err.message(), "Assertion failed: total should be 4");
+    assert_eq!(
+        eval_source(&mut vm, "try { @assert(0, 'zero') } catch e { e->kind }"),
+        Ok(value::Value::String("AssertionFailed".to_string()))
+    );
+}
+
+#[test]
+fn test_map_indexed() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@map_indexed([0, 1, 3], fn(i, v) => i * v)"),
+        Ok(value::Value::List(vec![value::Value::Int(0), value::Value::Int(1), value::Value::Int(6)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@map_indexed(['a', 'b'], fn(i, v) => (i, v))"),
+        Ok(value::Value::List(vec![
+            value::Value::Tuple(vec![value::Value::Int(0), value::Value::String("a".to_string())]),
+            value::Value::Tuple(vec![value::Value::Int(1), value::Value::String("b".to_string())]),
+        ]))
+    );
+}
+
+#[test]
+fn test_format_placeholders() {
+    let mut vm = Vm::new();
+    assert_eq!(
+        eval_source(&mut vm, "@format('{} + {} = {}', 1, 2.5, [3])"),
+        Ok(value::Value::String("1 + 2.5 = [3]".to_string()))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@format('{{}} {{x}} {}}}', 'y')"),
+        Ok(value::Value::String("{} {x} y}".to_string()))
+    );
+    assert_eq!(eval_source(&mut vm, "@format('plain')"), Ok(value::Value::String("plain".to_string())));
+    assert_eq!(
+        eval_source(&mut vm, "@format('{} {}', 1)").unwrap_err().message(),
+        "Invalid argument for format: more placeholders than arguments"
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@format('{}', 1, 2)").unwrap_err().message(),
+        "Invalid argument for format: more arguments than placeholders"
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@format('a } b')").unwrap_err().message(),
+        "Invalid argument for format: unmatched } in template"
+    );
+}
+
+#[test]
+fn test_take_while_and_drop_while() {
+    let mut vm = Vm::new();
+    let ints = |ns: &[i64]| Ok(value::Value::List(ns.iter().map(|n| value::Value::Int(*n)).collect()));
+    assert_eq!(eval_source(&mut vm, "@take_while([1, 2, 5, 1, 2], fn(x) => x < 3)"), ints(&[1, 2]));
+    assert_eq!(eval_source(&mut vm, "@drop_while([1, 2, 5, 1, 2], fn(x) => x < 3)"), ints(&[5, 1, 2]));
+    assert_eq!(eval_source(&mut vm, "@take_while([1, 2], fn(x) => true)"), ints(&[1, 2]));
+    assert_eq!(eval_source(&mut vm, "@drop_while([1, 2], fn(x) => true)"), ints(&[]));
+    // The predicate is not called past the first element it rejects.
+    assert_eq!(eval_source(&mut vm, "@take_while([1, 0, 'x'], fn(x) => x > 0)"), ints(&[1]));
+}
+
+#[test]
+fn test_flat_map() {
+    let mut vm = Vm::new();
+    let ints = |ns: &[i64]| Ok(value::Value::List(ns.iter().map(|n| value::Value::Int(*n)).collect()));
+    assert_eq!(eval_source(&mut vm, "@flat_map([1, 2], fn(x) => [x, x])"), ints(&[1, 1, 2, 2]));
+    assert_eq!(eval_source(&mut vm, "@flat_map([1, 2], fn(x) => [])"), ints(&[]));
+    assert!(matches!(
+        eval_source(&mut vm, "@flat_map([1, 2], fn(x) => x)"),
+        Err(crate::errors::Error::TypeMismatch(_))
+    ));
+}
+
+#[test]
+fn test_partition() {
+    let mut vm = Vm::new();
+    let ints = |ns: &[i64]| value::Value::List(ns.iter().map(|n| value::Value::Int(*n)).collect());
+    assert_eq!(
+        eval_source(&mut vm, "@partition([1, 2, 3, 4, 5, 6], fn(x) => (x / 2 * 2) == x)"),
+        Ok(value::Value::Tuple(vec![ints(&[2, 4, 6]), ints(&[1, 3, 5])]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@partition([], fn(x) => true)"),
+        Ok(value::Value::Tuple(vec![ints(&[]), ints(&[])]))
+    );
+}
+
+#[test]
+fn test_set_attr() {
+    let mut vm = Vm::new();
+    let source = "struct P { x; y }
+let mut p = @P{ x => 1; y => 2 }
+if true { p->x := p->x + 10 }
+[p->x, p->y]";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Ok(value::Value::List(vec![value::Value::Int(11), value::Value::Int(2)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "struct P { x }\nlet mut p = @P{ x => 1 }\np->z := 3"),
+        Err(crate::errors::Error::AttrNotFound(crate::errors::AttrNotFoundError { attr_name: "z".to_string() }))
+    );
+    assert!(matches!(
+        eval_source(&mut vm, "struct P { x }\nlet p = @P{ x => 1 }\np->x := 3"),
+        Err(crate::errors::Error::CannotMutate(_))
+    ));
+    assert_eq!(crate::format::format_source("p->x := 1 + 2").unwrap(), "p->x := 1 + 2\n");
+}
+
+#[test]
+fn test_method_calls_method_on_self() {
+    let mut vm = Vm::new();
+    let source = "struct P { x }
+impl P def quad() { let d = self->@double()
+d * 2 }
+impl P def double() { self->x * 2 }
+let p = @P{ x => 3 }
+p->@quad()";
+    assert_eq!(eval_source(&mut vm, source), Ok(value::Value::Int(12)));
+}
+
+#[test]
+fn test_enum_values() {
+    let mut vm = Vm::new();
+    let out = capture_output(&mut vm);
+    eval_source(&mut vm, "enum Color { Red; Green }\n@println(Color::Red)").unwrap();
+    assert_eq!(String::from_utf8(out.borrow().clone()).unwrap(), "Color::Red\n");
+    assert_eq!(
+        eval_source(&mut vm, "enum Color { Red; Green }\n[Color::Red == @Color{Red}, Color::Red == Color::Green, Color::Red != Color::Green]"),
+        Ok(value::Value::List(vec![value::Value::Bool(true), value::Value::Bool(false), value::Value::Bool(true)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "enum Color { Red; Green }\nmatch Color::Green { Color::Red => 'red', Color::Green => 'green', _ => 'other' }"),
+        Ok(value::Value::String("green".to_string()))
+    );
+    assert!(matches!(eval_source(&mut vm, "enum Color { Red; Green }\nColor::Blue"), Err(crate::errors::Error::FieldEnumNotFound(_))));
+}
+
+#[test]
+fn test_functions_compare_by_identity() {
+    let mut vm = Vm::new();
+    let source = "def f() { 1 }
+def g() { 1 }
+let h = f
+[@len(@unique([f, h, g, f])), f == h, f == g, @contains([g], f), @contains([g, h], f)]";
+    assert_eq!(
+        eval_source(&mut vm, source),
+        Ok(value::Value::List(vec![
+            value::Value::Int(2),
+            value::Value::Bool(true),
+            value::Value::Bool(false),
+            value::Value::Bool(false),
+            value::Value::Bool(true),
+        ]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@unique([1, 'a', 1, 2, 'a'])"),
+        Ok(value::Value::List(vec![value::Value::Int(1), value::Value::String("a".to_string()), value::Value::Int(2)]))
+    );
+    // The value a `def` returns is the function it binds.
+    assert_eq!(eval_source(&mut vm, "let d = def k() { 1 } [d == k, @contains([k], d)]").unwrap().to_string(), "[true, true]");
+}
+
+#[test]
+fn test_zip_and_zip_longest() {
+    let mut vm = Vm::new();
+    let pair = |a: i64, b: i64| value::Value::Tuple(vec![value::Value::Int(a), value::Value::Int(b)]);
+    assert_eq!(
+        eval_source(&mut vm, "@zip_longest([1, 2, 3], [9], 0)"),
+        Ok(value::Value::List(vec![pair(1, 9), pair(2, 0), pair(3, 0)]))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "@zip_longest([9], [1, 2], 0)"),
+        Ok(value::Value::List(vec![pair(9, 1), pair(0, 2)]))
+    );
+    assert_eq!(eval_source(&mut vm, "@zip([1, 2, 3], [9])"), Ok(value::Value::List(vec![pair(1, 9)])));
+}
+
+#[test]
+fn test_deep_recursion_shares_scopes() {
+    // Runs on the default test thread's stack: each call only clones the
+    // scope stack's `Rc`s, and `eval_node` keeps its arms in separate
+    // methods so that a nested expression costs one small frame.
+    let mut vm = Vm::new();
+    let result = eval_source(&mut vm, "def fib(n) { if n < 2 { return n } @fib(n - 1) + @fib(n - 2) }\n@fib(25)");
+    assert_eq!(result, Ok(value::Value::Int(75025)));
+}
+
+#[test]
+fn test_fractional_values_rejected_where_integers_required() {
+    let mut vm = Vm::new();
+    define_host_function(&mut vm, "noop", vec![], |_, _| Ok(value::Value::None));
+    let float_mismatch = Err(crate::errors::Error::TypeMismatch(crate::errors::TypeMismatchError {
+        expected: Type::Int,
+        found: Type::Float,
+    }));
+    for src in [
+        "let xs = [1, 2, 3] xs.(1.5)",
+        "let s = 'hello' s.(0.5)",
+        "(0:2.5)",
+        "let mut xs = [1, 2, 3] xs.(1.5) := 9",
+        "@range(0, 2.5)",
+        "@range(0, 6, 1.5)",
+        "@repeat(noop, 2.5)",
+        "@pad_left('ab', 4.5)",
+        "@pad_right('ab', 4.5)",
+        "'ab' * 2.5",
+        "2.5 * 'ab'",
+    ] {
+        assert_eq!(eval_source(&mut vm, src), float_mismatch, "{}", src);
+    }
+    assert_eq!(
+        eval_source(&mut vm, "@pad_left('ab', 0 - 1)"),
+        Err(crate::errors::Error::InvalidArgument(crate::errors::InvalidArgumentError {
+            name: "pad_left".to_string(),
+            reason: "width must not be negative, found -1".to_string(),
+        }))
+    );
+    assert_eq!(
+        eval_source(&mut vm, "'ab' * (0 - 2)"),
+        Err(crate::errors::Error::InvalidArgument(crate::errors::InvalidArgumentError {
+            name: "*".to_string(),
+            reason: "count must not be negative, found -2".to_string(),
+        }))
+    );
 }
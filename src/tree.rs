@@ -19,6 +19,15 @@ pub enum Expr {
         mutable: bool,
         type_: Option<crate::executer::value::Type>,
     },
+    AssignTuple {
+        names: Vec<String>,
+        value: Box<Expr>,
+        mutable: bool,
+    },
+    // `let a = 1, b = a + 1`, one `Assign` per name.
+    AssignMany {
+        assigns: Vec<Expr>,
+    },
     Literal {
         value: Literal,
     },
@@ -27,6 +36,15 @@ pub enum Expr {
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    UnaryOp {
+        op: UnaryOp,
+        value: Box<Expr>,
+    },
+    CustomOp {
+        op: String,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
     IOp {
         op: IOp,
         value: Box<Expr>,
@@ -46,6 +64,14 @@ pub enum Expr {
         name: String,
         args: Vec<Expr>,
     },
+    Lambda {
+        args: Vec<Expr>,
+        body: Box<Expr>,
+    },
+    Apply {
+        func: Box<Expr>,
+        args: Vec<Expr>,
+    },
     Block {
         body: Vec<Expr>,
     },
@@ -55,6 +81,12 @@ pub enum Expr {
     List {
         elems: Vec<Expr>,
     },
+    Tuple {
+        elems: Vec<Expr>,
+    },
+    Map {
+        entries: Vec<(Expr, Expr)>,
+    },
     Index {
         name: Box<Expr>,
         index: Box<Expr>,
@@ -69,11 +101,13 @@ pub enum Expr {
     },
     CallStruct {
         name: String,
+        base: Option<Box<Expr>>,
         args: Vec<(Expr, Expr)>,
     },
     GetAttr {
         name: String,
         attr: String,
+        optional: bool,
     },
     Impl {
         name_struct: String,
@@ -86,11 +120,26 @@ pub enum Expr {
         name: String,
         func: String,
         args: Vec<Expr>,
+        optional: bool,
     },
     SetVar {
         name: String,
         value: Box<Expr>,
     },
+    SetIndex {
+        name: String,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    SetAttr {
+        name: String,
+        attr: String,
+        value: Box<Expr>,
+    },
+    Swap {
+        a: String,
+        b: String,
+    },
     Match {
         value: Box<Expr>,
         cases: Vec<(Expr, Expr)>,
@@ -108,9 +157,77 @@ pub enum Expr {
         to: crate::executer::value::Type,
 
     },
+    TryCatch {
+        body: Box<Expr>,
+        name: String,
+        handler: Box<Expr>,
+    },
+    With {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Break,
+    Continue,
+    Return {
+        value: Box<Expr>,
+    },
+    Spread {
+        value: Box<Expr>,
+    },
     Empty
 }
 
+impl Expr {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Expr::IfThen { .. } => "IfThen",
+            Expr::IfThenElse { .. } => "IfThenElse",
+            Expr::While { .. } => "While",
+            Expr::Assign { .. } => "Assign",
+            Expr::AssignTuple { .. } => "AssignTuple",
+            Expr::AssignMany { .. } => "AssignMany",
+            Expr::Literal { .. } => "Literal",
+            Expr::BinOp { .. } => "BinOp",
+            Expr::CustomOp { .. } => "CustomOp",
+            Expr::UnaryOp { .. } => "UnaryOp",
+            Expr::IOp { .. } => "IOp",
+            Expr::For { .. } => "For",
+            Expr::FunDef { .. } => "FunDef",
+            Expr::Call { .. } => "Call",
+            Expr::Lambda { .. } => "Lambda",
+            Expr::Apply { .. } => "Apply",
+            Expr::Block { .. } => "Block",
+            Expr::Ident { .. } => "Ident",
+            Expr::List { .. } => "List",
+            Expr::Tuple { .. } => "Tuple",
+            Expr::Map { .. } => "Map",
+            Expr::Index { .. } => "Index",
+            Expr::Range { .. } => "Range",
+            Expr::StructDef { .. } => "StructDef",
+            Expr::CallStruct { .. } => "CallStruct",
+            Expr::GetAttr { .. } => "GetAttr",
+            Expr::Impl { .. } => "Impl",
+            Expr::GetFunc { .. } => "GetFunc",
+            Expr::SetVar { .. } => "SetVar",
+            Expr::SetIndex { .. } => "SetIndex",
+            Expr::SetAttr { .. } => "SetAttr",
+            Expr::Swap { .. } => "Swap",
+            Expr::Match { .. } => "Match",
+            Expr::Enum { .. } => "Enum",
+            Expr::EnumCall { .. } => "EnumCall",
+            Expr::To { .. } => "To",
+            Expr::TryCatch { .. } => "TryCatch",
+            Expr::With { .. } => "With",
+            Expr::Break => "Break",
+            Expr::Continue => "Continue",
+            Expr::Return { .. } => "Return",
+            Expr::Spread { .. } => "Spread",
+            Expr::Empty => "Empty",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Op {
     Add,
@@ -118,6 +235,7 @@ pub enum Op {
     Mul,
     Div,
     Mod,
+    Pow,
     Eq,
     Neq,
     Lt,
@@ -128,6 +246,12 @@ pub enum Op {
     Or
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnaryOp {
+    Neg,
+    Not
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum IOp {
     IAdd,
@@ -138,11 +262,35 @@ pub enum IOp {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal {
-    Number(f64),
+    Int(i64),
+    Float(f64),
     String(String),
     Bool(bool),
 }
 
+/// Resolves the escape sequences in the body of a string literal.
+pub fn unescape(raw: &str) -> Result<String, String> {
+    let mut s = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some('r') => s.push('\r'),
+            Some('\\') => s.push('\\'),
+            Some('\'') => s.push('\''),
+            Some('"') => s.push('"'),
+            Some(other) => return Err(format!("unknown escape sequence \\{} in string literal", other)),
+            None => return Err("unterminated escape sequence in string literal".to_string()),
+        }
+    }
+    Ok(s)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Type {
     StringType, 